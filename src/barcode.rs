@@ -0,0 +1,109 @@
+use crate::formats::read_luma;
+use crate::pattern::Color;
+
+use gstreamer::BufferRef;
+use gstreamer_video::VideoFrameRef;
+
+// The barcode is a block in the top left corner of the frame. Its size is
+// a fixed fraction of the frame, so it survives scaling. The block is a
+// black border around a grid of cells, one bit each, white for a one.
+const DIVISIONS: u32 = 40;
+const BLOCK: u32 = 10;
+const GRID: u32 = BLOCK - 2;
+
+// The value is 48 bits, followed by a 16 bit check so misreads are rejected.
+const VALUE_BITS: u32 = 48;
+const VALUE_MASK: u64 = (1 << VALUE_BITS) - 1;
+
+// Frames smaller than this don't have room for a barcode.
+const MIN_SIZE: u32 = DIVISIONS;
+
+fn check(value: u64) -> u64 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in &value.to_le_bytes()[..(VALUE_BITS / 8) as usize] {
+        hash ^= u32::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    u64::from((hash ^ (hash >> 16)) as u16)
+}
+
+fn encode(value: u64) -> u64 {
+    let value = value & VALUE_MASK;
+    (value << 16) | check(value)
+}
+
+fn decode(bits: u64) -> Option<u64> {
+    let value = bits >> 16;
+    if bits & 0xffff == check(value) {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+// The start of a division of a line of the given length.
+fn division_start(division: u32, length: u32) -> u32 {
+    division * length / DIVISIONS
+}
+
+// Paints the barcode for the given value over line `y` of the frame.
+// Only the low 48 bits of the value are encoded.
+pub fn paint_barcode_line(value: u64, width: u32, height: u32, y: u32, line: &mut [Color]) {
+    if width < MIN_SIZE || height < MIN_SIZE || y >= division_start(BLOCK, height) {
+        return;
+    }
+    let bits = encode(value);
+    let row = y * DIVISIONS / height;
+    for (x, pixel) in line
+        .iter_mut()
+        .enumerate()
+        .take(division_start(BLOCK, width) as usize)
+    {
+        let column = x as u32 * DIVISIONS / width;
+        let border = row == 0 || column == 0 || row == BLOCK - 1 || column == BLOCK - 1;
+        let one = !border && bits & (1 << (63 - ((row - 1) * GRID + column - 1))) != 0;
+        *pixel = if one {
+            Color::gray(255)
+        } else {
+            Color::gray(0)
+        };
+    }
+}
+
+// Reads a barcode painted by paint_barcode_line, if there is one.
+// Each cell is read from the average luma of its middle, away from
+// edges that scaling and compression blur.
+pub fn read_barcode(frame: &VideoFrameRef<&BufferRef>) -> Option<u64> {
+    let width = frame.width();
+    let height = frame.height();
+    if width < MIN_SIZE || height < MIN_SIZE {
+        return None;
+    }
+    let mut bits = 0;
+    for row in 1..=GRID {
+        let top = division_start(row, height);
+        let bottom = division_start(row + 1, height);
+        let (top, bottom) = middle(top, bottom);
+        for column in 1..=GRID {
+            let left = division_start(column, width);
+            let right = division_start(column + 1, width);
+            let (left, right) = middle(left, right);
+            let mut sum = 0;
+            let mut count = 0;
+            for y in top..bottom {
+                for x in left..right {
+                    sum += u32::from(read_luma(frame, x, y)?);
+                    count += 1;
+                }
+            }
+            bits = (bits << 1) | u64::from(sum >= count * 128);
+        }
+    }
+    decode(bits)
+}
+
+// The middle half of a range, or all of it if that is too small to split.
+fn middle(start: u32, end: u32) -> (u32, u32) {
+    let quarter = (end - start) / 4;
+    (start + quarter, end - quarter)
+}
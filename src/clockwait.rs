@@ -0,0 +1,98 @@
+use gstreamer::Clock;
+use gstreamer::ClockError;
+use gstreamer::ClockExt;
+use gstreamer::ClockExtManual;
+use gstreamer::ClockId;
+use gstreamer::ClockTime;
+use gstreamer::ClockTimeDiff;
+use gstreamer::ElementExt;
+use gstreamer::FlowError;
+use gstreamer::SystemClock;
+use gstreamer_base::prelude::BaseSrcExtManual;
+use gstreamer_base::BaseSrc;
+
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct State {
+    clock_id: Option<ClockId>,
+    flushing: bool,
+}
+
+// A wait on the pipeline clock for live sources, which unlock can interrupt.
+#[derive(Default)]
+pub struct ClockWait {
+    state: Mutex<State>,
+}
+
+impl ClockWait {
+    // Waits until the clock reaches the running time of a position in the stream,
+    // returning the jitter. Without a clock there's nothing to wait for.
+    pub fn wait_until(
+        &self,
+        src: &BaseSrc,
+        position: ClockTime,
+    ) -> Result<ClockTimeDiff, FlowError> {
+        let clock = match src.get_clock() {
+            Some(clock) => clock,
+            None => return Ok(0),
+        };
+        let segment = src.get_segment();
+        let running_time = match segment.downcast_ref::<ClockTime>() {
+            Some(segment) => segment.to_running_time(position),
+            None => position,
+        };
+        self.wait(&clock, src.get_base_time() + running_time)
+    }
+
+    // Waits for a duration to pass, on the system clock if the pipeline has none,
+    // so a source that has nothing to push doesn't spin.
+    pub fn wait_for(&self, src: &BaseSrc, duration: ClockTime) -> Result<(), FlowError> {
+        if duration.is_none() {
+            return self.check_flushing();
+        }
+        let clock = src.get_clock().unwrap_or_else(SystemClock::obtain);
+        self.wait(&clock, clock.get_time() + duration).map(|_| ())
+    }
+
+    // Whether unlock has been called without unlock_stop.
+    pub fn check_flushing(&self) -> Result<(), FlowError> {
+        if self.state.lock().unwrap().flushing {
+            Err(FlowError::Flushing)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn wait(&self, clock: &Clock, time: ClockTime) -> Result<ClockTimeDiff, FlowError> {
+        let clock_id = match clock.new_single_shot_id(time) {
+            Some(clock_id) => clock_id,
+            None => return self.check_flushing().map(|_| 0),
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.flushing {
+                return Err(FlowError::Flushing);
+            }
+            state.clock_id = Some(clock_id.clone());
+        }
+        let (result, jitter) = clock_id.wait();
+        self.state.lock().unwrap().clock_id = None;
+        match result {
+            Err(ClockError::Unscheduled) => Err(FlowError::Flushing),
+            _ => Ok(jitter),
+        }
+    }
+
+    pub fn unlock(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(clock_id) = state.clock_id.take() {
+            clock_id.unschedule();
+        }
+        state.flushing = true;
+    }
+
+    pub fn unlock_stop(&self) {
+        self.state.lock().unwrap().flushing = false;
+    }
+}
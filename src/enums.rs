@@ -0,0 +1,44 @@
+use glib::translate::from_glib;
+use glib::EnumClass;
+use glib::EnumValue;
+use glib::Type;
+use glib::Value;
+
+use std::ffi::CString;
+use std::ptr;
+
+// glib doesn't let us derive enum types yet, so register them by hand.
+// The value table has to live for the rest of the program, so it is leaked.
+pub fn register_enum_type(name: &str, values: &[(i32, &str, &str)]) -> Type {
+    let mut enum_values: Vec<gobject_sys::GEnumValue> = values
+        .iter()
+        .map(|&(value, name, nick)| gobject_sys::GEnumValue {
+            value,
+            value_name: CString::new(name).unwrap().into_raw(),
+            value_nick: CString::new(nick).unwrap().into_raw(),
+        })
+        .collect();
+    enum_values.push(gobject_sys::GEnumValue {
+        value: 0,
+        value_name: ptr::null(),
+        value_nick: ptr::null(),
+    });
+    let enum_values = Box::leak(enum_values.into_boxed_slice());
+    let name = CString::new(name).unwrap();
+    unsafe {
+        from_glib(gobject_sys::g_enum_register_static(
+            name.as_ptr(),
+            enum_values.as_ptr(),
+        ))
+    }
+}
+
+pub fn enum_to_value(type_: Type, value: i32) -> Value {
+    EnumClass::new(type_)
+        .and_then(|class| class.to_value(value))
+        .expect("Unregistered enum value")
+}
+
+pub fn enum_from_value(value: &Value) -> Option<i32> {
+    EnumValue::from_value(value).map(|value| value.get_value())
+}
@@ -0,0 +1,415 @@
+use crate::barcode::paint_barcode_line;
+use crate::pattern::Color;
+use crate::pattern::Paint;
+use crate::pattern::PatternParams;
+
+use gstreamer::BufferRef;
+use gstreamer_video::VideoColorMatrix;
+use gstreamer_video::VideoColorRange;
+use gstreamer_video::VideoFormat;
+use gstreamer_video::VideoFrameRef;
+use gstreamer_video::VideoInfo;
+
+pub const FORMATS: [VideoFormat; 10] = [
+    VideoFormat::Bgrx,
+    VideoFormat::Rgba,
+    VideoFormat::Rgb,
+    VideoFormat::Gray8,
+    VideoFormat::Gray16Le,
+    VideoFormat::Gray16Be,
+    VideoFormat::I420,
+    VideoFormat::Nv12,
+    VideoFormat::Yuy2,
+    VideoFormat::Uyvy,
+];
+
+// Converts colours to Y'CbCr using the negotiated colorimetry.
+pub struct YuvMatrix {
+    kr: f32,
+    kb: f32,
+    full_range: bool,
+}
+
+impl YuvMatrix {
+    pub fn new(info: &VideoInfo) -> YuvMatrix {
+        let colorimetry = info.colorimetry();
+        let (kr, kb) = match colorimetry.matrix() {
+            VideoColorMatrix::Bt709 => (0.2126, 0.0722),
+            VideoColorMatrix::Bt2020 => (0.2627, 0.0593),
+            VideoColorMatrix::Smpte240m => (0.212, 0.087),
+            VideoColorMatrix::Fcc => (0.30, 0.11),
+            _ => (0.299, 0.114),
+        };
+        let full_range = colorimetry.range() == VideoColorRange::Range0255;
+        YuvMatrix { kr, kb, full_range }
+    }
+
+    // Luma in 0..1
+    fn luma(&self, color: Color) -> f32 {
+        let r = f32::from(color.r) / 255.0;
+        let g = f32::from(color.g) / 255.0;
+        let b = f32::from(color.b) / 255.0;
+        self.kr * r + (1.0 - self.kr - self.kb) * g + self.kb * b
+    }
+
+    // Chroma in -0.5..0.5
+    fn chroma(&self, color: Color) -> (f32, f32) {
+        let y = self.luma(color);
+        let b = f32::from(color.b) / 255.0;
+        let r = f32::from(color.r) / 255.0;
+        (
+            (b - y) / (2.0 - 2.0 * self.kb),
+            (r - y) / (2.0 - 2.0 * self.kr),
+        )
+    }
+
+    fn y(&self, color: Color) -> u8 {
+        let y = self.luma(color);
+        if self.full_range {
+            quantize(y * 255.0)
+        } else {
+            quantize(16.0 + y * 219.0)
+        }
+    }
+
+    // The chroma of a block of pixels is the average of their chroma.
+    fn uv<'a, I: IntoIterator<Item = &'a Color>>(&self, block: I) -> (u8, u8) {
+        let mut count = 0.0;
+        let mut u_sum = 0.0;
+        let mut v_sum = 0.0;
+        for color in block {
+            let (u, v) = self.chroma(*color);
+            u_sum += u;
+            v_sum += v;
+            count += 1.0;
+        }
+        let scale = if self.full_range { 255.0 } else { 224.0 };
+        (
+            quantize(128.0 + scale * u_sum / count),
+            quantize(128.0 + scale * v_sum / count),
+        )
+    }
+}
+
+fn quantize(value: f32) -> u8 {
+    (value + 0.5).clamp(0.0, 255.0) as u8
+}
+
+fn gray16(matrix: &YuvMatrix, color: Color) -> u16 {
+    (matrix.luma(color) * 65535.0 + 0.5).clamp(0.0, 65535.0) as u16
+}
+
+// Paints a line of the pattern, with the barcode over it if there is one.
+fn paint_line(painter: &dyn Paint, params: &PatternParams, y: u32, line: &mut [Color]) {
+    painter.paint_line(params, y, line);
+    if let Some(barcode) = params.barcode {
+        paint_barcode_line(barcode, params.width, params.height, y, line);
+    }
+}
+
+// Paints the pattern or image into a mapped frame of any of the supported formats.
+// Returns false if the format isn't supported.
+pub fn paint_frame(
+    painter: &dyn Paint,
+    params: &PatternParams,
+    frame: &mut VideoFrameRef<&mut BufferRef>,
+) -> bool {
+    let format = frame.format();
+    let width = frame.width() as usize;
+    let height = frame.height() as usize;
+    let matrix = YuvMatrix::new(frame.info());
+    let mut colors = vec![Color::default(); width];
+
+    match format {
+        VideoFormat::I420 | VideoFormat::Nv12 => {
+            paint_420(painter, params, &matrix, frame);
+            return true;
+        }
+        VideoFormat::Bgrx
+        | VideoFormat::Rgba
+        | VideoFormat::Rgb
+        | VideoFormat::Gray8
+        | VideoFormat::Gray16Le
+        | VideoFormat::Gray16Be
+        | VideoFormat::Yuy2
+        | VideoFormat::Uyvy => (),
+        _ => return false,
+    }
+
+    let stride = frame.plane_stride()[0] as usize;
+    let data = frame.plane_data_mut(0).unwrap();
+    for (y, line) in data.chunks_mut(stride).take(height).enumerate() {
+        paint_line(painter, params, y as u32, &mut colors);
+        match format {
+            VideoFormat::Bgrx => {
+                for (pixel, color) in line.chunks_exact_mut(4).zip(&colors) {
+                    pixel[0] = color.b;
+                    pixel[1] = color.g;
+                    pixel[2] = color.r;
+                    pixel[3] = 0;
+                }
+            }
+            VideoFormat::Rgba => {
+                for (pixel, color) in line.chunks_exact_mut(4).zip(&colors) {
+                    pixel[0] = color.r;
+                    pixel[1] = color.g;
+                    pixel[2] = color.b;
+                    pixel[3] = 255;
+                }
+            }
+            VideoFormat::Rgb => {
+                for (pixel, color) in line.chunks_exact_mut(3).zip(&colors) {
+                    pixel[0] = color.r;
+                    pixel[1] = color.g;
+                    pixel[2] = color.b;
+                }
+            }
+            VideoFormat::Gray8 => {
+                for (pixel, color) in line.iter_mut().zip(&colors) {
+                    *pixel = (gray16(&matrix, *color) >> 8) as u8;
+                }
+            }
+            VideoFormat::Gray16Le => {
+                for (pixel, color) in line.chunks_exact_mut(2).zip(&colors) {
+                    pixel.copy_from_slice(&gray16(&matrix, *color).to_le_bytes());
+                }
+            }
+            VideoFormat::Gray16Be => {
+                for (pixel, color) in line.chunks_exact_mut(2).zip(&colors) {
+                    pixel.copy_from_slice(&gray16(&matrix, *color).to_be_bytes());
+                }
+            }
+            VideoFormat::Yuy2 | VideoFormat::Uyvy => {
+                // Each macropixel is two luma samples sharing one chroma sample.
+                for (macropixel, pair) in line.chunks_exact_mut(4).zip(colors.chunks(2)) {
+                    let y0 = matrix.y(pair[0]);
+                    let y1 = matrix.y(pair[pair.len() - 1]);
+                    let (u, v) = matrix.uv(pair);
+                    if format == VideoFormat::Yuy2 {
+                        macropixel.copy_from_slice(&[y0, u, y1, v]);
+                    } else {
+                        macropixel.copy_from_slice(&[u, y0, v, y1]);
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    true
+}
+
+// 4:2:0 formats need two lines of the pattern for each line of chroma.
+fn paint_420(
+    painter: &dyn Paint,
+    params: &PatternParams,
+    matrix: &YuvMatrix,
+    frame: &mut VideoFrameRef<&mut BufferRef>,
+) {
+    let format = frame.format();
+    let width = frame.width() as usize;
+    let height = frame.height() as usize;
+    let chroma_width = width.div_ceil(2);
+    let chroma_height = height.div_ceil(2);
+    let mut lines = [vec![Color::default(); width], vec![Color::default(); width]];
+    let mut chroma = Vec::with_capacity(chroma_width * chroma_height);
+
+    let y_stride = frame.plane_stride()[0] as usize;
+    let y_data = frame.plane_data_mut(0).unwrap();
+    for chroma_y in 0..chroma_height {
+        let rows = if chroma_y * 2 + 1 < height { 2 } else { 1 };
+        for (row, line) in lines[..rows].iter_mut().enumerate() {
+            let y = chroma_y * 2 + row;
+            paint_line(painter, params, y as u32, line);
+            let y_line = &mut y_data[y * y_stride..y * y_stride + width];
+            for (luma, color) in y_line.iter_mut().zip(line.iter()) {
+                *luma = matrix.y(*color);
+            }
+        }
+        for chroma_x in 0..chroma_width {
+            let x = chroma_x * 2;
+            let end = (x + 2).min(width);
+            let block = lines[..rows].iter().flat_map(|line| &line[x..end]);
+            chroma.push(matrix.uv(block));
+        }
+    }
+
+    if format == VideoFormat::I420 {
+        for &(plane, is_v) in &[(1, false), (2, true)] {
+            let stride = frame.plane_stride()[plane as usize] as usize;
+            let data = frame.plane_data_mut(plane).unwrap();
+            for (line, chroma_line) in data.chunks_mut(stride).zip(chroma.chunks(chroma_width)) {
+                for (sample, uv) in line.iter_mut().zip(chroma_line) {
+                    *sample = if is_v { uv.1 } else { uv.0 };
+                }
+            }
+        }
+    } else {
+        let stride = frame.plane_stride()[1] as usize;
+        let data = frame.plane_data_mut(1).unwrap();
+        for (line, chroma_line) in data.chunks_mut(stride).zip(chroma.chunks(chroma_width)) {
+            for (sample, uv) in line.chunks_exact_mut(2).zip(chroma_line) {
+                sample[0] = uv.0;
+                sample[1] = uv.1;
+            }
+        }
+    }
+}
+
+// The luma of a pixel, near enough, for analysing frames in any of the supported formats.
+pub fn read_luma(frame: &VideoFrameRef<&BufferRef>, x: u32, y: u32) -> Option<u8> {
+    let stride = frame.plane_stride()[0] as usize;
+    let data = frame.plane_data(0)?;
+    let line = data.get(y as usize * stride..)?;
+    let x = x as usize;
+    let rgb_luma = |r: u8, g: u8, b: u8| {
+        ((77 * u32::from(r) + 150 * u32::from(g) + 29 * u32::from(b)) >> 8) as u8
+    };
+    match frame.format() {
+        VideoFormat::Bgrx => {
+            let pixel = line.get(x * 4..x * 4 + 3)?;
+            Some(rgb_luma(pixel[2], pixel[1], pixel[0]))
+        }
+        VideoFormat::Rgba => {
+            let pixel = line.get(x * 4..x * 4 + 3)?;
+            Some(rgb_luma(pixel[0], pixel[1], pixel[2]))
+        }
+        VideoFormat::Rgb => {
+            let pixel = line.get(x * 3..x * 3 + 3)?;
+            Some(rgb_luma(pixel[0], pixel[1], pixel[2]))
+        }
+        VideoFormat::Gray8 | VideoFormat::I420 | VideoFormat::Nv12 => line.get(x).cloned(),
+        VideoFormat::Gray16Le => line.get(x * 2 + 1).cloned(),
+        VideoFormat::Gray16Be => line.get(x * 2).cloned(),
+        VideoFormat::Yuy2 => line.get(x * 2).cloned(),
+        VideoFormat::Uyvy => line.get(x * 2 + 1).cloned(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Pattern;
+
+    use gstreamer::Buffer;
+
+    use std::time::Duration;
+
+    const SIZES: [(u32, u32); 3] = [(1, 1), (3, 3), (5, 2)];
+
+    fn params(width: u32, height: u32) -> PatternParams {
+        PatternParams {
+            width,
+            height,
+            time: Duration::from_millis(500),
+            frame: 15,
+            foreground: Color::from_argb(0xffff_ffff),
+            seed: 0,
+            barcode: None,
+        }
+    }
+
+    // Paints a frame into a buffer that starts out zeroed.
+    fn paint(format: VideoFormat, width: u32, height: u32, pattern: Pattern) -> Buffer {
+        let info = VideoInfo::new(format, width, height).build().unwrap();
+        let mut buffer = Buffer::with_size(info.size()).unwrap();
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.map_writable().unwrap().as_mut_slice().fill(0);
+            let mut frame = VideoFrameRef::from_buffer_ref_writable(buffer, &info).unwrap();
+            assert!(paint_frame(&pattern, &params(width, height), &mut frame));
+        }
+        buffer
+    }
+
+    // The chroma samples of each row, for the subsampled formats.
+    fn chroma(frame: &VideoFrameRef<&BufferRef>) -> Vec<u8> {
+        let width = frame.width() as usize;
+        let chroma_width = width.div_ceil(2);
+        let chroma_height = (frame.height() as usize).div_ceil(2);
+        let plane = |index: u32, row_bytes: usize, rows: usize| {
+            let stride = frame.plane_stride()[index as usize] as usize;
+            let data = frame.plane_data(index).unwrap();
+            (0..rows)
+                .flat_map(|y| data[y * stride..y * stride + row_bytes].to_vec())
+                .collect::<Vec<u8>>()
+        };
+        match frame.format() {
+            VideoFormat::I420 => {
+                let mut samples = plane(1, chroma_width, chroma_height);
+                samples.extend(plane(2, chroma_width, chroma_height));
+                samples
+            }
+            VideoFormat::Nv12 => plane(1, chroma_width * 2, chroma_height),
+            VideoFormat::Yuy2 | VideoFormat::Uyvy => {
+                let offset = if frame.format() == VideoFormat::Yuy2 {
+                    1
+                } else {
+                    0
+                };
+                plane(0, chroma_width * 4, frame.height() as usize)
+                    .into_iter()
+                    .skip(offset)
+                    .step_by(2)
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn paints_every_pattern_at_odd_sizes() {
+        gstreamer::init().unwrap();
+        for &format in &FORMATS {
+            for &(width, height) in &SIZES {
+                for value in 0.. {
+                    let pattern = match Pattern::from_i32(value) {
+                        Some(pattern) => pattern,
+                        None => break,
+                    };
+                    paint(format, width, height, pattern);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fills_odd_sized_frames() {
+        gstreamer::init().unwrap();
+        for &format in &FORMATS {
+            for &(width, height) in &SIZES {
+                let buffer = paint(format, width, height, Pattern::Solid);
+                let info = VideoInfo::new(format, width, height).build().unwrap();
+                let frame =
+                    VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), &info).unwrap();
+                for y in 0..height {
+                    for x in 0..width {
+                        let luma = read_luma(&frame, x, y).unwrap();
+                        assert!(
+                            luma > 200,
+                            "{:?} {}x{} has luma {} at {},{}",
+                            format,
+                            width,
+                            height,
+                            luma,
+                            x,
+                            y
+                        );
+                    }
+                }
+                // White has no colour, so all the chroma is in the middle
+                for sample in chroma(&frame) {
+                    assert!(
+                        (i32::from(sample) - 128).abs() <= 1,
+                        "{:?} {}x{} has chroma {}",
+                        format,
+                        width,
+                        height,
+                        sample
+                    );
+                }
+            }
+        }
+    }
+}
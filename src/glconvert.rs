@@ -0,0 +1,163 @@
+use crate::glshader::render_target;
+use crate::glshader::texture_function;
+use crate::glshader::FragmentShader;
+use crate::glshader::GlCheck;
+use crate::glshader::GlError;
+use crate::glshader::ShaderError;
+
+use sparkle::gl;
+use sparkle::gl::Gl;
+
+use gstreamer_gl::GLContext;
+use gstreamer_gl::GLContextExt;
+use gstreamer_gl::GLAPI;
+use gstreamer_video::VideoFormat;
+use gstreamer_video::VideoInfo;
+
+// BT.601 in limited range, from RGB in [0, 1] to YUV in [0, 1].
+const YUV: &str = "float luma(vec3 c) {
+    return (16.0 + dot(c, vec3(65.481, 128.553, 24.966))) / 255.0;
+}
+float chroma_u(vec3 c) {
+    return (128.0 + dot(c, vec3(-37.797, -74.203, 112.0))) / 255.0;
+}
+float chroma_v(vec3 c) {
+    return (128.0 + dot(c, vec3(112.0, -93.786, -18.214))) / 255.0;
+}
+";
+
+// Frames are drawn in RGBA, so formats with other channel orders or more than
+// one plane are drawn to a texture first, then converted a plane at a time.
+pub struct Converter {
+    texture: gl::GLuint,
+    fbo: gl::GLuint,
+    // One per plane, drawn with the plane's texture bound to the framebuffer.
+    planes: Vec<FragmentShader>,
+}
+
+impl Converter {
+    // Runs on the GL thread. Formats which can be drawn directly don't need converting.
+    pub fn new(
+        gl: &Gl,
+        gl_context: &GLContext,
+        info: &VideoInfo,
+        debug: bool,
+    ) -> Result<Option<Converter>, ShaderError> {
+        let outputs: &[&str] = match info.format() {
+            // The texture's channels are in memory order
+            VideoFormat::Argb64 => &["vec4(a, c)"],
+            VideoFormat::Nv12 => &[
+                "vec4(luma(c), 0.0, 0.0, 1.0)",
+                "vec4(chroma_u(c), chroma_v(c), 0.0, 1.0)",
+            ],
+            VideoFormat::I420 => &[
+                "vec4(luma(c), 0.0, 0.0, 1.0)",
+                "vec4(chroma_u(c), 0.0, 0.0, 1.0)",
+                "vec4(chroma_v(c), 0.0, 0.0, 1.0)",
+            ],
+            _ => return Ok(None),
+        };
+        let api = gl_context.get_gl_api();
+        let mut planes = Vec::new();
+        for output in outputs {
+            match FragmentShader::new(gl, api, &plane_source(api, output), debug) {
+                Ok(shader) => planes.push(shader),
+                Err(err) => {
+                    for shader in planes {
+                        shader.delete(gl);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        let width = info.width() as i32;
+        let height = info.height() as i32;
+        let check = GlCheck::new(gl, debug);
+        // 16-bit formats would lose precision in an 8-bit texture
+        let internal_format = match info.format() {
+            VideoFormat::Argb64 => gl::RGBA16F,
+            _ => gl::RGBA,
+        };
+        let (texture, fbo) = render_target(gl, &check, internal_format, width, height);
+        // So chroma averages the pixels it covers
+        gl.bind_texture(gl::TEXTURE_2D, texture);
+        gl.tex_parameter_i(
+            gl::TEXTURE_2D,
+            gl::TEXTURE_MIN_FILTER,
+            gl::LINEAR as gl::GLint,
+        );
+        gl.tex_parameter_i(
+            gl::TEXTURE_2D,
+            gl::TEXTURE_MAG_FILTER,
+            gl::LINEAR as gl::GLint,
+        );
+        check.after("glTexParameteri");
+        gl.bind_texture(gl::TEXTURE_2D, 0);
+        let converter = Converter {
+            texture,
+            fbo,
+            planes,
+        };
+        if let Err(err) = check.finish("Creating the conversion texture") {
+            converter.delete(gl);
+            return Err(ShaderError::Gl(err));
+        }
+        Ok(Some(converter))
+    }
+
+    // The RGBA texture frames are drawn to.
+    pub fn texture(&self) -> gl::GLuint {
+        self.texture
+    }
+
+    // Runs on the GL thread, binding the framebuffer of the RGBA texture.
+    pub fn bind(&self, gl: &Gl, check: &GlCheck) {
+        gl.bind_framebuffer(gl::FRAMEBUFFER, self.fbo);
+        check.after("glBindFramebuffer");
+    }
+
+    // Runs on the GL thread, with the plane's texture bound to the framebuffer.
+    pub fn draw_plane(
+        &self,
+        gl: &Gl,
+        debug: bool,
+        plane: usize,
+        width: i32,
+        height: i32,
+    ) -> Result<(), GlError> {
+        gl.active_texture(gl::TEXTURE0);
+        gl.bind_texture(gl::TEXTURE_2D, self.texture);
+        let result = self.planes[plane].draw(gl, debug, width, height, 0.0, 0);
+        gl.bind_texture(gl::TEXTURE_2D, 0);
+        result
+    }
+
+    // Runs on the GL thread
+    pub fn delete(self, gl: &Gl) {
+        for shader in self.planes {
+            shader.delete(gl);
+        }
+        gl.delete_framebuffers(&[self.fbo]);
+        gl.delete_textures(&[self.texture]);
+    }
+}
+
+// Each plane covers the whole frame, so subsampled chroma is sampled
+// between the pixels it covers.
+fn plane_source(api: GLAPI, output: &str) -> String {
+    format!(
+        "uniform sampler2D myglsrc_frame;
+{}
+void mainImage(out vec4 fragColor, in vec2 fragCoord) {{
+    vec4 rgba = {}(myglsrc_frame, fragCoord / iResolution.xy);
+    vec3 c = rgba.rgb;
+    float a = rgba.a;
+    fragColor = {};
+}}
+",
+        YUV,
+        texture_function(api),
+        output
+    )
+}
@@ -0,0 +1,70 @@
+use glib::glib_object_wrapper;
+use glib::glib_sys::gboolean;
+use glib::glib_wrapper;
+use glib::subclass::types::IsSubclassable;
+use glib::subclass::types::ObjectSubclass;
+use glib::translate::from_glib;
+use glib::translate::FromGlibPtrNone;
+use glib::translate::ToGlib;
+use glib::translate::ToGlibPtr;
+use gstreamer::subclass::element::ElementImpl;
+use gstreamer::subclass::PanicPoison;
+use gstreamer_gl_sys::gst_gl_filter_get_type;
+use gstreamer_gl_sys::gst_gl_filter_render_to_target;
+use gstreamer_gl_sys::GstGLFilter;
+use gstreamer_gl_sys::GstGLFilterClass;
+use gstreamer_gl_sys::GstGLMemory;
+
+use std::os::raw::c_void;
+
+// gstreamer-gl doesn't wrap GstGLFilter, so elements based on it override its
+// vfuncs themselves in class_init. BaseTransform comes first so the class derefs
+// to BaseTransformClass, since GLBaseFilter's wrapper only extends Object.
+glib_wrapper! {
+    pub struct GLFilter(Object<GstGLFilter, GstGLFilterClass, GLFilterClass>)
+        @extends gstreamer_base::BaseTransform, gstreamer_gl::GLBaseFilter, gstreamer::Element, gstreamer::Object;
+
+    match fn {
+        get_type => || gst_gl_filter_get_type(),
+    }
+}
+
+unsafe impl Send for GLFilter {}
+unsafe impl Sync for GLFilter {}
+
+unsafe impl<T: ObjectSubclass + ElementImpl> IsSubclassable<T> for GLFilterClass
+where
+    <T as ObjectSubclass>::Instance: PanicPoison,
+{
+    fn override_vfuncs(&mut self) {
+        <gstreamer::ElementClass as IsSubclassable<T>>::override_vfuncs(self);
+    }
+}
+
+// Draws to the output texture's framebuffer on the GL thread, with the input
+// texture passed to the closure.
+pub fn render_to_target<F: FnMut(&GstGLMemory) -> bool>(
+    filter: &GLFilter,
+    input: &GstGLMemory,
+    output: &GstGLMemory,
+    f: F,
+) -> bool {
+    unsafe extern "C" fn trampoline<F: FnMut(&GstGLMemory) -> bool>(
+        _filter: *mut GstGLFilter,
+        input: *mut GstGLMemory,
+        data: *mut c_void,
+    ) -> gboolean {
+        let f = &mut *(data as *mut F);
+        f(&*input).to_glib()
+    }
+    let mut f = f;
+    unsafe {
+        from_glib(gst_gl_filter_render_to_target(
+            filter.to_glib_none().0,
+            input as *const GstGLMemory as *mut GstGLMemory,
+            output as *const GstGLMemory as *mut GstGLMemory,
+            Some(trampoline::<F>),
+            &mut f as *mut F as *mut c_void,
+        ))
+    }
+}
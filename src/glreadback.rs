@@ -0,0 +1,289 @@
+use crate::glshader::check_error;
+use crate::glshader::render_target;
+use crate::glshader::texture_function;
+use crate::glshader::FragmentShader;
+use crate::glshader::GlCheck;
+use crate::glshader::GlError;
+use crate::glshader::ShaderError;
+
+use sparkle::gl;
+use sparkle::gl::Gl;
+
+use gstreamer_gl::GLContext;
+use gstreamer_gl::GLContextExt;
+use gstreamer_gl::GLAPI;
+use gstreamer_video::VideoFormat;
+use gstreamer_video::VideoInfo;
+
+use std::ptr;
+use std::slice;
+
+// Pixels on their way back from the GPU.
+pub enum Pixels {
+    // Still in a pixel buffer object, which is mapped when they're needed
+    Pbo(gl::GLuint),
+    // Already read, when the context has no pixel buffer objects
+    Read(Vec<u8>),
+}
+
+// Reads RGBA frames back into system memory, converting them to the output format
+// with a shader. The converted frame is packed into an RGBA texture, byte for byte
+// as it's laid out in memory, so one glReadPixels gets the whole buffer.
+pub struct Readback {
+    size: usize,
+    width: i32,
+    height: i32,
+    // None for RGBA, which is read straight from the frame.
+    shader: Option<FragmentShader>,
+    texture: gl::GLuint,
+    fbo: gl::GLuint,
+    // Two are enough for one frame in flight while the next one renders.
+    pbos: Vec<gl::GLuint>,
+    next_pbo: usize,
+}
+
+impl Readback {
+    // Runs on the GL thread
+    pub fn new(
+        gl: &Gl,
+        gl_context: &GLContext,
+        info: &VideoInfo,
+        debug: bool,
+    ) -> Result<Readback, ShaderError> {
+        let api = gl_context.get_gl_api();
+        let (width, height, source) = match info.format() {
+            VideoFormat::Bgrx => (
+                info.width() as i32,
+                info.height() as i32,
+                Some(bgrx_source(api)),
+            ),
+            VideoFormat::I420 => {
+                let stride = info.stride()[0] as usize;
+                let rows = info.size().div_ceil(stride);
+                (stride as i32 / 4, rows as i32, Some(i420_source(api, info)))
+            }
+            _ => (info.width() as i32, info.height() as i32, None),
+        };
+        let shader = match source {
+            Some(source) => Some(FragmentShader::new(gl, api, &source, debug)?),
+            None => None,
+        };
+
+        let check = GlCheck::new(gl, debug);
+        let (texture, fbo) = if shader.is_some() {
+            render_target(gl, &check, gl::RGBA, width, height)
+        } else {
+            (0, 0)
+        };
+
+        // Mapping pixel buffers needs GL 3 or GLES 3
+        let pbos = if gl_context.check_gl_version(GLAPI::OPENGL | GLAPI::OPENGL3, 3, 0)
+            || gl_context.check_gl_version(GLAPI::GLES2, 3, 0)
+        {
+            let pbos = gl.gen_buffers(2);
+            check.after("glGenBuffers");
+            for &pbo in &pbos {
+                gl.bind_buffer(gl::PIXEL_PACK_BUFFER, pbo);
+                check.after("glBindBuffer");
+                unsafe {
+                    gl.buffer_data(
+                        gl::PIXEL_PACK_BUFFER,
+                        (width * height * 4) as gl::GLsizeiptr,
+                        ptr::null(),
+                        gl::STREAM_READ,
+                    )
+                };
+                check.after("glBufferData");
+            }
+            gl.bind_buffer(gl::PIXEL_PACK_BUFFER, 0);
+            pbos
+        } else {
+            Vec::new()
+        };
+
+        let readback = Readback {
+            size: info.size(),
+            width,
+            height,
+            shader,
+            texture,
+            fbo,
+            pbos,
+            next_pbo: 0,
+        };
+        if let Err(err) = check.finish("Creating the readback resources") {
+            readback.delete(gl);
+            return Err(ShaderError::Gl(err));
+        }
+        Ok(readback)
+    }
+
+    // Runs on the GL thread, with the frame's texture bound to the framebuffer.
+    // With pixel buffer objects this doesn't wait for the GPU.
+    pub fn start(
+        &mut self,
+        gl: &Gl,
+        debug: bool,
+        texture_id: gl::GLuint,
+    ) -> Result<Pixels, GlError> {
+        let check = GlCheck::new(gl, debug);
+        if let Some(ref shader) = self.shader {
+            gl.bind_framebuffer(gl::FRAMEBUFFER, self.fbo);
+            check.after("glBindFramebuffer");
+            gl.active_texture(gl::TEXTURE0);
+            gl.bind_texture(gl::TEXTURE_2D, texture_id);
+            check.after("glBindTexture");
+            let result = shader.draw(gl, debug, self.width, self.height, 0.0, 0);
+            gl.bind_texture(gl::TEXTURE_2D, 0);
+            result?;
+        }
+
+        // Rows are a multiple of four bytes, so need no padding
+        gl.pixel_store_i(gl::PACK_ALIGNMENT, 4);
+        let pixels = match self.pbos.get(self.next_pbo) {
+            Some(&pbo) => {
+                self.next_pbo = (self.next_pbo + 1) % self.pbos.len();
+                gl.bind_buffer(gl::PIXEL_PACK_BUFFER, pbo);
+                check.after("glBindBuffer");
+                unsafe {
+                    gl.read_pixels_into_pixel_pack_buffer(
+                        0,
+                        0,
+                        self.width,
+                        self.height,
+                        gl::RGBA,
+                        gl::UNSIGNED_BYTE,
+                        0,
+                    )
+                };
+                check.after("glReadPixels");
+                gl.bind_buffer(gl::PIXEL_PACK_BUFFER, 0);
+                // Start the copy now, rather than when the buffer is mapped
+                gl.flush();
+                Pixels::Pbo(pbo)
+            }
+            None => {
+                let data =
+                    gl.read_pixels(0, 0, self.width, self.height, gl::RGBA, gl::UNSIGNED_BYTE);
+                check.after("glReadPixels");
+                Pixels::Read(data)
+            }
+        };
+        check.finish("Reading back the frame")?;
+        Ok(pixels)
+    }
+
+    // Runs on the GL thread, copying the frame into a buffer of `size` bytes.
+    pub fn finish(
+        &self,
+        gl: &Gl,
+        debug: bool,
+        pixels: Pixels,
+        data: &mut [u8],
+    ) -> Result<(), GlError> {
+        let len = data.len().min(self.size);
+        let pbo = match pixels {
+            Pixels::Pbo(pbo) => pbo,
+            Pixels::Read(pixels) => {
+                data[..len].copy_from_slice(&pixels[..len]);
+                return Ok(());
+            }
+        };
+        let check = GlCheck::new(gl, debug);
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, pbo);
+        check.after("glBindBuffer");
+        let mapped = gl.map_buffer_range(
+            gl::PIXEL_PACK_BUFFER,
+            0,
+            len as gl::GLsizeiptr,
+            gl::MAP_READ_BIT,
+        );
+        if mapped.is_null() {
+            let result = check_error(gl, "glMapBufferRange");
+            gl.bind_buffer(gl::PIXEL_PACK_BUFFER, 0);
+            return result;
+        }
+        data[..len].copy_from_slice(unsafe { slice::from_raw_parts(mapped as *const u8, len) });
+        gl.unmap_buffer(gl::PIXEL_PACK_BUFFER);
+        check.after("glUnmapBuffer");
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, 0);
+        check.finish("Copying the frame")
+    }
+
+    // Runs on the GL thread
+    pub fn delete(self, gl: &Gl) {
+        gl.delete_buffers(&self.pbos);
+        if let Some(shader) = self.shader {
+            shader.delete(gl);
+            gl.delete_framebuffers(&[self.fbo]);
+            gl.delete_textures(&[self.texture]);
+        }
+    }
+}
+
+fn bgrx_source(api: GLAPI) -> String {
+    format!(
+        "uniform sampler2D myglsrc_frame;
+void mainImage(out vec4 fragColor, in vec2 fragCoord) {{
+    fragColor = vec4({}(myglsrc_frame, fragCoord / iResolution.xy).bgr, 1.0);
+}}
+",
+        texture_function(api)
+    )
+}
+
+// Each output pixel is four consecutive bytes of the I420 buffer, whose layout
+// comes from the video info. Colours are converted with BT.601 in limited range,
+// and chroma is sampled between the four pixels it covers.
+fn i420_source(api: GLAPI, info: &VideoInfo) -> String {
+    let stride = info.stride();
+    let offset = info.offset();
+    format!(
+        "uniform sampler2D myglsrc_frame;
+const vec2 size = vec2({:.1}, {:.1});
+const vec3 stride = vec3({:.1}, {:.1}, {:.1});
+const vec3 offset = vec3({:.1}, {:.1}, {:.1});
+
+vec3 rgb(vec2 pixel) {{
+    return {}(myglsrc_frame, pixel / size).rgb;
+}}
+
+vec2 plane_pixel(float index, float row_stride) {{
+    float row = floor((index + 0.5) / row_stride);
+    return vec2(index - row * row_stride, row);
+}}
+
+float plane_byte(float index) {{
+    if (index < offset.y) {{
+        vec2 pixel = plane_pixel(index - offset.x, stride.x);
+        if (pixel.x >= size.x || pixel.y >= size.y) return 0.0;
+        return (16.0 + dot(rgb(pixel + 0.5), vec3(65.481, 128.553, 24.966))) / 255.0;
+    }}
+    bool v = index >= offset.z;
+    vec2 pixel = v ? plane_pixel(index - offset.z, stride.z) : plane_pixel(index - offset.y, stride.y);
+    if (pixel.x >= ceil(size.x / 2.0) || pixel.y >= ceil(size.y / 2.0)) return 0.0;
+    vec3 coeffs = v ? vec3(112.0, -93.786, -18.214) : vec3(-37.797, -74.203, 112.0);
+    return (128.0 + dot(rgb(min(pixel * 2.0 + 1.0, size)), coeffs)) / 255.0;
+}}
+
+void mainImage(out vec4 fragColor, in vec2 fragCoord) {{
+    float index = (floor(fragCoord.y) * iResolution.x + floor(fragCoord.x)) * 4.0;
+    fragColor = vec4(
+        plane_byte(index),
+        plane_byte(index + 1.0),
+        plane_byte(index + 2.0),
+        plane_byte(index + 3.0)
+    );
+}}
+",
+        info.width() as f32,
+        info.height() as f32,
+        stride[0] as f32,
+        stride[1] as f32,
+        stride[2] as f32,
+        offset[0] as f32,
+        offset[1] as f32,
+        offset[2] as f32,
+        texture_function(api),
+    )
+}
@@ -0,0 +1,481 @@
+use crate::glshader::compile;
+use crate::glshader::GlCheck;
+use crate::glshader::GlError;
+use crate::glshader::ShaderError;
+
+use sparkle::gl;
+use sparkle::gl::Gl;
+
+use gstreamer_gl::GLAPI;
+
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+
+// Each vertex is a position then a normal.
+const VERTEX_FLOATS: usize = 6;
+
+const VERTEX_SHADER: &str = "uniform mat4 view_projection;
+void main() {
+    v_position = a_position;
+    v_normal = a_normal;
+    gl_Position = view_projection * vec4(a_position, 1.0);
+}
+";
+
+// Phong lighting, with a white light at the camera. Faces can be seen from
+// either side, so normals facing away from the camera are turned round.
+const FRAGMENT_SHADER: &str = "uniform vec3 camera;
+const vec3 colour = vec3(0.8, 0.8, 0.8);
+void main() {
+    vec3 n = normalize(v_normal);
+    vec3 v = normalize(camera - v_position);
+    if (dot(n, v) < 0.0) n = -n;
+    float diffuse = max(dot(n, v), 0.0);
+    float specular = pow(max(dot(reflect(-v, n), v), 0.0), 32.0);
+    myglsrc_FragColor = vec4(colour * (0.1 + 0.7 * diffuse) + vec3(0.3 * specular), 1.0);
+}
+";
+
+pub struct ObjError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+// Triangles from an OBJ file, with its bounding sphere.
+pub struct Mesh {
+    vertices: Vec<f32>,
+    centre: [f32; 3],
+    radius: f32,
+}
+
+impl Mesh {
+    // Only positions, normals and faces are used. Faces without normals are flat
+    // shaded, and polygons are split into fans of triangles.
+    pub fn from_obj(source: &str) -> Result<Mesh, ObjError> {
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut normals: Vec<[f32; 3]> = Vec::new();
+        let mut vertices = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: String| ObjError {
+                line: index + 1,
+                message,
+            };
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("v") => positions.push(parse_vec3(words).map_err(error)?),
+                Some("vn") => normals.push(parse_vec3(words).map_err(error)?),
+                Some("f") => {
+                    let corners = words
+                        .map(|word| parse_corner(word, positions.len(), normals.len()))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(error)?;
+                    if corners.len() < 3 {
+                        return Err(error(String::from("Face has fewer than 3 vertices")));
+                    }
+                    for i in 1..corners.len() - 1 {
+                        let triangle = [corners[0], corners[i], corners[i + 1]];
+                        let p = [
+                            positions[triangle[0].0],
+                            positions[triangle[1].0],
+                            positions[triangle[2].0],
+                        ];
+                        let flat = cross(sub(p[1], p[0]), sub(p[2], p[0]));
+                        for (&(_, normal), &position) in triangle.iter().zip(&p) {
+                            let normal = normal.map_or(flat, |normal| normals[normal]);
+                            vertices.extend_from_slice(&position);
+                            vertices.extend_from_slice(&normal);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        if vertices.is_empty() {
+            return Err(ObjError {
+                line: source.lines().count(),
+                message: String::from("No faces"),
+            });
+        }
+
+        // The sphere around the bounding box is close enough to set the depth range
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for position in vertices.chunks_exact(VERTEX_FLOATS) {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(position[axis]);
+                max[axis] = max[axis].max(position[axis]);
+            }
+        }
+        let centre = scale(add(min, max), 0.5);
+        let radius = length(sub(max, centre));
+        Ok(Mesh {
+            vertices,
+            centre,
+            radius,
+        })
+    }
+}
+
+// The camera, in the same coordinates as the mesh. Its field of view is vertical, in degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub position: [f32; 3],
+    pub target: [f32; 3],
+    pub fov: f32,
+}
+
+impl Camera {
+    // A column-major matrix, with the near and far planes just around the mesh.
+    // GL memory has the top row first, so the image is flipped vertically.
+    fn view_projection(&self, mesh: &Mesh, aspect: f32) -> [f32; 16] {
+        let forward = normalize(sub(self.target, self.position));
+        let up = if forward[0].abs() < 1e-6 && forward[2].abs() < 1e-6 {
+            [0.0, 0.0, -1.0]
+        } else {
+            [0.0, 1.0, 0.0]
+        };
+        let right = normalize(cross(forward, up));
+        let up = cross(right, forward);
+
+        let distance = dot(sub(mesh.centre, self.position), forward);
+        let far = (distance + mesh.radius).max(1e-3) * 1.01;
+        let near = (distance - mesh.radius).max(far / 1000.0);
+        let f = 1.0 / (self.fov.to_radians() / 2.0).tan();
+        let a = (far + near) / (near - far);
+        let b = 2.0 * far * near / (near - far);
+
+        let eye = self.position;
+        let rows = [
+            scale4([right[0], right[1], right[2], -dot(right, eye)], f / aspect),
+            scale4([up[0], up[1], up[2], -dot(up, eye)], -f),
+            add4(
+                scale4(
+                    [-forward[0], -forward[1], -forward[2], dot(forward, eye)],
+                    a,
+                ),
+                [0.0, 0.0, 0.0, b],
+            ),
+            [forward[0], forward[1], forward[2], -dot(forward, eye)],
+        ];
+        let mut matrix = [0.0; 16];
+        for (row, values) in rows.iter().enumerate() {
+            for (column, value) in values.iter().enumerate() {
+                matrix[column * 4 + row] = *value;
+            }
+        }
+        matrix
+    }
+}
+
+// A scene file, loaded from its location.
+pub struct Scene {
+    pub location: String,
+    pub mesh: Mesh,
+}
+
+// Draws a scene with a depth buffer the size of the frame.
+pub struct SceneRenderer {
+    scene: Arc<Scene>,
+    program: gl::GLuint,
+    vao: Option<gl::GLuint>,
+    vbo: gl::GLuint,
+    view_projection: gl::GLint,
+    camera: gl::GLint,
+    depth: Option<(gl::GLuint, i32, i32)>,
+}
+
+impl SceneRenderer {
+    // Runs on the GL thread
+    pub fn new(
+        gl: &Gl,
+        api: GLAPI,
+        scene: Arc<Scene>,
+        debug: bool,
+    ) -> Result<SceneRenderer, ShaderError> {
+        let (vertex_header, fragment_header) = if api.contains(GLAPI::OPENGL3) {
+            (
+                "#version 150\nin vec3 a_position;\nin vec3 a_normal;\nout vec3 v_position;\nout vec3 v_normal;\n",
+                "#version 150\nin vec3 v_position;\nin vec3 v_normal;\nout vec4 myglsrc_FragColor;\n",
+            )
+        } else if api.contains(GLAPI::GLES2) {
+            (
+                "#version 100\nattribute vec3 a_position;\nattribute vec3 a_normal;\nvarying vec3 v_position;\nvarying vec3 v_normal;\n",
+                "#version 100\nprecision highp float;\nvarying vec3 v_position;\nvarying vec3 v_normal;\n#define myglsrc_FragColor gl_FragColor\n",
+            )
+        } else {
+            (
+                "#version 110\nattribute vec3 a_position;\nattribute vec3 a_normal;\nvarying vec3 v_position;\nvarying vec3 v_normal;\n",
+                "#version 110\nvarying vec3 v_position;\nvarying vec3 v_normal;\n#define myglsrc_FragColor gl_FragColor\n",
+            )
+        };
+        let vertex_shader = compile(
+            gl,
+            debug,
+            gl::VERTEX_SHADER,
+            &[vertex_header, VERTEX_SHADER],
+        )?;
+        let fragment_shader = match compile(
+            gl,
+            debug,
+            gl::FRAGMENT_SHADER,
+            &[fragment_header, FRAGMENT_SHADER],
+        ) {
+            Ok(fragment_shader) => fragment_shader,
+            Err(err) => {
+                gl.delete_shader(vertex_shader);
+                return Err(err);
+            }
+        };
+
+        let check = GlCheck::new(gl, debug);
+        let program = gl.create_program();
+        check.after("glCreateProgram");
+        gl.attach_shader(program, vertex_shader);
+        gl.attach_shader(program, fragment_shader);
+        check.after("glAttachShader");
+        gl.bind_attrib_location(program, 0, "a_position");
+        gl.bind_attrib_location(program, 1, "a_normal");
+        check.after("glBindAttribLocation");
+        gl.link_program(program);
+        check.after("glLinkProgram");
+        gl.delete_shader(vertex_shader);
+        gl.delete_shader(fragment_shader);
+        check.after("glDeleteShader");
+        let mut status = [0];
+        unsafe { gl.get_program_iv(program, gl::LINK_STATUS, &mut status) };
+        if status[0] == 0 {
+            let log = gl.get_program_info_log(program);
+            gl.delete_program(program);
+            return Err(ShaderError::Link(log));
+        }
+
+        // Core profiles need a vertex array object to draw anything.
+        let vao = if api.contains(GLAPI::OPENGL3) {
+            let vao = gl.gen_vertex_arrays(1)[0];
+            check.after("glGenVertexArrays");
+            Some(vao)
+        } else {
+            None
+        };
+        let vbo = gl.gen_buffers(1)[0];
+        check.after("glGenBuffers");
+        gl.bind_buffer(gl::ARRAY_BUFFER, vbo);
+        check.after("glBindBuffer");
+        let vertices = &scene.mesh.vertices;
+        unsafe {
+            gl.buffer_data(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(&vertices[..]) as gl::GLsizeiptr,
+                vertices.as_ptr() as *const gl::GLvoid,
+                gl::STATIC_DRAW,
+            )
+        };
+        check.after("glBufferData");
+        gl.bind_buffer(gl::ARRAY_BUFFER, 0);
+
+        let renderer = SceneRenderer {
+            scene,
+            program,
+            vao,
+            vbo,
+            view_projection: gl.get_uniform_location(program, "view_projection"),
+            camera: gl.get_uniform_location(program, "camera"),
+            depth: None,
+        };
+        if let Err(err) = check.finish("Creating the scene") {
+            renderer.delete(gl);
+            return Err(ShaderError::Gl(err));
+        }
+        Ok(renderer)
+    }
+
+    pub fn scene(&self) -> &Arc<Scene> {
+        &self.scene
+    }
+
+    // Runs on the GL thread, drawing to the currently bound framebuffer.
+    pub fn draw(
+        &mut self,
+        gl: &Gl,
+        debug: bool,
+        camera: &Camera,
+        width: i32,
+        height: i32,
+    ) -> Result<(), GlError> {
+        let check = GlCheck::new(gl, debug);
+
+        // The depth buffer is attached to whichever framebuffer we're drawing to
+        let depth = match self.depth {
+            Some((depth, w, h)) if w == width && h == height => depth,
+            _ => {
+                if let Some((depth, ..)) = self.depth.take() {
+                    gl.delete_renderbuffers(&[depth]);
+                }
+                let depth = gl.gen_renderbuffers(1)[0];
+                check.after("glGenRenderbuffers");
+                gl.bind_renderbuffer(gl::RENDERBUFFER, depth);
+                check.after("glBindRenderbuffer");
+                gl.renderbuffer_storage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT16, width, height);
+                check.after("glRenderbufferStorage");
+                gl.bind_renderbuffer(gl::RENDERBUFFER, 0);
+                self.depth = Some((depth, width, height));
+                depth
+            }
+        };
+        gl.framebuffer_renderbuffer(
+            gl::FRAMEBUFFER,
+            gl::DEPTH_ATTACHMENT,
+            gl::RENDERBUFFER,
+            depth,
+        );
+        check.after("glFramebufferRenderbuffer");
+
+        gl.viewport(0, 0, width, height);
+        check.after("glViewport");
+        gl.enable(gl::DEPTH_TEST);
+        gl.clear_color(0.0, 0.0, 0.0, 1.0);
+        gl.clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        check.after("glClear");
+
+        let aspect = width as f32 / height.max(1) as f32;
+        let view_projection = camera.view_projection(&self.scene.mesh, aspect);
+        gl.use_program(self.program);
+        check.after("glUseProgram");
+        gl.uniform_matrix_4fv(self.view_projection, false, &view_projection);
+        let [x, y, z] = camera.position;
+        gl.uniform_3f(self.camera, x, y, z);
+        check.after("glUniform");
+        if let Some(vao) = self.vao {
+            gl.bind_vertex_array(vao);
+            check.after("glBindVertexArray");
+        }
+        gl.bind_buffer(gl::ARRAY_BUFFER, self.vbo);
+        check.after("glBindBuffer");
+        let stride = (VERTEX_FLOATS * mem::size_of::<f32>()) as gl::GLsizei;
+        gl.vertex_attrib_pointer_f32(0, 3, false, stride, 0);
+        gl.vertex_attrib_pointer_f32(1, 3, false, stride, 3 * mem::size_of::<f32>() as u32);
+        check.after("glVertexAttribPointer");
+        gl.enable_vertex_attrib_array(0);
+        gl.enable_vertex_attrib_array(1);
+        check.after("glEnableVertexAttribArray");
+        let count = self.scene.mesh.vertices.len() / VERTEX_FLOATS;
+        gl.draw_arrays(gl::TRIANGLES, 0, count as gl::GLsizei);
+        check.after("glDrawArrays");
+        gl.disable_vertex_attrib_array(0);
+        gl.disable_vertex_attrib_array(1);
+        gl.bind_buffer(gl::ARRAY_BUFFER, 0);
+        if self.vao.is_some() {
+            gl.bind_vertex_array(0);
+        }
+        gl.use_program(0);
+        gl.disable(gl::DEPTH_TEST);
+
+        // The framebuffers belong to the pool's textures, so we don't leave it attached
+        gl.framebuffer_renderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, 0);
+        check.finish("Drawing the scene")
+    }
+
+    // Runs on the GL thread
+    pub fn delete(self, gl: &Gl) {
+        if let Some((depth, ..)) = self.depth {
+            gl.delete_renderbuffers(&[depth]);
+        }
+        gl.delete_buffers(&[self.vbo]);
+        if let Some(vao) = self.vao {
+            gl.delete_vertex_arrays(&[vao]);
+        }
+        gl.delete_program(self.program);
+    }
+}
+
+fn parse_vec3<'a, I: Iterator<Item = &'a str>>(words: I) -> Result<[f32; 3], String> {
+    let mut vec3 = [0.0; 3];
+    let mut words = words;
+    for value in &mut vec3 {
+        let word = words
+            .next()
+            .ok_or_else(|| String::from("Too few coordinates"))?;
+        *value = word
+            .parse()
+            .map_err(|_| format!("{} isn't a number", word))?;
+    }
+    Ok(vec3)
+}
+
+// A face's vertex is `v`, `v/vt`, `v//vn` or `v/vt/vn`, with indices from 1,
+// or from the end if they're negative.
+fn parse_corner(
+    word: &str,
+    positions: usize,
+    normals: usize,
+) -> Result<(usize, Option<usize>), String> {
+    let mut indices = word.split('/');
+    let position = parse_index(indices.next().unwrap_or(""), positions)?;
+    let normal = match indices.nth(1) {
+        Some(normal) if !normal.is_empty() => Some(parse_index(normal, normals)?),
+        _ => None,
+    };
+    Ok((position, normal))
+}
+
+fn parse_index(word: &str, count: usize) -> Result<usize, String> {
+    let index: isize = word
+        .parse()
+        .map_err(|_| format!("{} isn't an index", word))?;
+    let resolved = if index < 0 {
+        count as isize + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved as usize >= count {
+        return Err(format!("Index {} is out of range", index));
+    }
+    Ok(resolved as usize)
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: [f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn length(a: [f32; 3]) -> f32 {
+    dot(a, a).sqrt()
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    scale(a, 1.0 / length(a).max(f32::MIN_POSITIVE))
+}
+
+fn scale4(a: [f32; 4], s: f32) -> [f32; 4] {
+    [a[0] * s, a[1] * s, a[2] * s, a[3] * s]
+}
+
+fn add4(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]]
+}
@@ -0,0 +1,364 @@
+use sparkle::gl;
+use sparkle::gl::Gl;
+
+use gstreamer_gl::GLAPI;
+
+use std::cell::Cell;
+use std::fmt;
+use std::mem;
+
+// A full-screen quad, drawn as a triangle strip.
+const QUAD: [f32; 8] = [-1.0, -1.0, 1.0, -1.0, -1.0, 1.0, 1.0, 1.0];
+
+#[derive(Clone, Copy, Debug)]
+pub struct GlError {
+    call: &'static str,
+    code: gl::GLenum,
+}
+
+impl fmt::Display for GlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.code {
+            gl::INVALID_ENUM => "GL_INVALID_ENUM",
+            gl::INVALID_VALUE => "GL_INVALID_VALUE",
+            gl::INVALID_OPERATION => "GL_INVALID_OPERATION",
+            gl::INVALID_FRAMEBUFFER_OPERATION => "GL_INVALID_FRAMEBUFFER_OPERATION",
+            gl::OUT_OF_MEMORY => "GL_OUT_OF_MEMORY",
+            _ => return write!(f, "{} failed with GL error {:#x}", self.call, self.code),
+        };
+        write!(f, "{} failed with {}", self.call, name)
+    }
+}
+
+pub fn check_error(gl: &Gl, call: &'static str) -> Result<(), GlError> {
+    match gl.get_error() {
+        gl::NO_ERROR => Ok(()),
+        code => Err(GlError { call, code }),
+    }
+}
+
+// Checks a sequence of GL calls for errors. In debug mode every call is checked,
+// so the error names the call that caused it, otherwise just the end of the sequence.
+pub struct GlCheck<'a> {
+    gl: &'a Gl,
+    debug: bool,
+    error: Cell<Option<GlError>>,
+}
+
+impl<'a> GlCheck<'a> {
+    pub fn new(gl: &'a Gl, debug: bool) -> GlCheck<'a> {
+        GlCheck {
+            gl,
+            debug,
+            error: Cell::new(None),
+        }
+    }
+
+    pub fn after(&self, call: &'static str) {
+        if self.debug && self.error.get().is_none() {
+            self.error.set(check_error(self.gl, call).err());
+        }
+    }
+
+    pub fn finish(self, call: &'static str) -> Result<(), GlError> {
+        match self.error.get() {
+            Some(err) => Err(err),
+            None => check_error(self.gl, call),
+        }
+    }
+}
+
+// Makes an RGBA texture with a framebuffer to draw into it. The internal format
+// is RGBA for 8 bits per channel, or RGBA16F for more.
+pub fn render_target(
+    gl: &Gl,
+    check: &GlCheck,
+    internal_format: gl::GLenum,
+    width: i32,
+    height: i32,
+) -> (gl::GLuint, gl::GLuint) {
+    let pixel_type = match internal_format {
+        gl::RGBA16F => gl::HALF_FLOAT,
+        _ => gl::UNSIGNED_BYTE,
+    };
+    let texture = gl.gen_textures(1)[0];
+    check.after("glGenTextures");
+    gl.bind_texture(gl::TEXTURE_2D, texture);
+    check.after("glBindTexture");
+    gl.tex_image_2d(
+        gl::TEXTURE_2D,
+        0,
+        internal_format as gl::GLint,
+        width,
+        height,
+        0,
+        gl::RGBA,
+        pixel_type,
+        gl::TexImageSource::Pixels(None),
+    );
+    check.after("glTexImage2D");
+    gl.tex_parameter_i(
+        gl::TEXTURE_2D,
+        gl::TEXTURE_MIN_FILTER,
+        gl::NEAREST as gl::GLint,
+    );
+    gl.tex_parameter_i(
+        gl::TEXTURE_2D,
+        gl::TEXTURE_MAG_FILTER,
+        gl::NEAREST as gl::GLint,
+    );
+    check.after("glTexParameteri");
+    gl.bind_texture(gl::TEXTURE_2D, 0);
+    let fbo = gl.gen_framebuffers(1)[0];
+    check.after("glGenFramebuffers");
+    gl.bind_framebuffer(gl::FRAMEBUFFER, fbo);
+    check.after("glBindFramebuffer");
+    gl.framebuffer_texture_2d(
+        gl::FRAMEBUFFER,
+        gl::COLOR_ATTACHMENT0,
+        gl::TEXTURE_2D,
+        texture,
+        0,
+    );
+    check.after("glFramebufferTexture2D");
+    gl.bind_framebuffer(gl::FRAMEBUFFER, 0);
+    (texture, fbo)
+}
+
+// GLSL 1.50 renamed texture2D
+pub fn texture_function(api: GLAPI) -> &'static str {
+    if api.contains(GLAPI::OPENGL3) {
+        "texture"
+    } else {
+        "texture2D"
+    }
+}
+
+pub enum ShaderError {
+    Compile(String),
+    Link(String),
+    Gl(GlError),
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShaderError::Compile(ref log) => write!(f, "Compile error: {}", log),
+            ShaderError::Link(ref log) => write!(f, "Link error: {}", log),
+            ShaderError::Gl(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+// A Shadertoy-style fragment shader, which defines
+// `void mainImage(out vec4 fragColor, in vec2 fragCoord)`
+// and can use the `iTime`, `iResolution` and `iFrame` uniforms.
+pub struct FragmentShader {
+    source: String,
+    program: gl::GLuint,
+    vao: Option<gl::GLuint>,
+    vbo: gl::GLuint,
+    time: gl::GLint,
+    resolution: gl::GLint,
+    frame: gl::GLint,
+}
+
+impl FragmentShader {
+    // Runs on the GL thread
+    pub fn new(
+        gl: &Gl,
+        api: GLAPI,
+        source: &str,
+        debug: bool,
+    ) -> Result<FragmentShader, ShaderError> {
+        // GL 3 core profiles only support GLSL 1.50 and up, GLES 2 only GLSL 1.00.
+        let (vertex_header, fragment_header, fragment_main) = if api.contains(GLAPI::OPENGL3) {
+            (
+                "#version 150\nin vec2 a_position;\n",
+                "#version 150\nout vec4 myglsrc_FragColor;\n",
+                "void main() { mainImage(myglsrc_FragColor, gl_FragCoord.xy); }\n",
+            )
+        } else if api.contains(GLAPI::GLES2) {
+            (
+                "#version 100\nattribute vec2 a_position;\n",
+                "#version 100\nprecision highp float;\n",
+                "void main() { mainImage(gl_FragColor, gl_FragCoord.xy); }\n",
+            )
+        } else {
+            (
+                "#version 110\nattribute vec2 a_position;\n",
+                "#version 110\n",
+                "void main() { mainImage(gl_FragColor, gl_FragCoord.xy); }\n",
+            )
+        };
+        let uniforms = "uniform float iTime;\nuniform vec3 iResolution;\nuniform int iFrame;\n";
+
+        let vertex_shader = compile(
+            gl,
+            debug,
+            gl::VERTEX_SHADER,
+            &[
+                vertex_header,
+                "void main() { gl_Position = vec4(a_position, 0.0, 1.0); }\n",
+            ],
+        )?;
+        let fragment_shader = match compile(
+            gl,
+            debug,
+            gl::FRAGMENT_SHADER,
+            &[fragment_header, uniforms, source, "\n", fragment_main],
+        ) {
+            Ok(fragment_shader) => fragment_shader,
+            Err(err) => {
+                gl.delete_shader(vertex_shader);
+                return Err(err);
+            }
+        };
+
+        let check = GlCheck::new(gl, debug);
+        let program = gl.create_program();
+        check.after("glCreateProgram");
+        gl.attach_shader(program, vertex_shader);
+        check.after("glAttachShader");
+        gl.attach_shader(program, fragment_shader);
+        check.after("glAttachShader");
+        gl.bind_attrib_location(program, 0, "a_position");
+        check.after("glBindAttribLocation");
+        gl.link_program(program);
+        check.after("glLinkProgram");
+        gl.delete_shader(vertex_shader);
+        gl.delete_shader(fragment_shader);
+        check.after("glDeleteShader");
+        let mut status = [0];
+        unsafe { gl.get_program_iv(program, gl::LINK_STATUS, &mut status) };
+        if status[0] == 0 {
+            let log = gl.get_program_info_log(program);
+            gl.delete_program(program);
+            return Err(ShaderError::Link(log));
+        }
+
+        // Core profiles need a vertex array object to draw anything.
+        let vao = if api.contains(GLAPI::OPENGL3) {
+            let vao = gl.gen_vertex_arrays(1)[0];
+            check.after("glGenVertexArrays");
+            gl.bind_vertex_array(vao);
+            check.after("glBindVertexArray");
+            Some(vao)
+        } else {
+            None
+        };
+        let vbo = gl.gen_buffers(1)[0];
+        check.after("glGenBuffers");
+        gl.bind_buffer(gl::ARRAY_BUFFER, vbo);
+        check.after("glBindBuffer");
+        unsafe {
+            gl.buffer_data(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(&QUAD) as gl::GLsizeiptr,
+                QUAD.as_ptr() as *const gl::GLvoid,
+                gl::STATIC_DRAW,
+            )
+        };
+        check.after("glBufferData");
+        gl.bind_buffer(gl::ARRAY_BUFFER, 0);
+        if vao.is_some() {
+            gl.bind_vertex_array(0);
+        }
+
+        let shader = FragmentShader {
+            source: String::from(source),
+            program,
+            vao,
+            vbo,
+            time: gl.get_uniform_location(program, "iTime"),
+            resolution: gl.get_uniform_location(program, "iResolution"),
+            frame: gl.get_uniform_location(program, "iFrame"),
+        };
+        if let Err(err) = check.finish("Creating the shader program") {
+            shader.delete(gl);
+            return Err(ShaderError::Gl(err));
+        }
+        Ok(shader)
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // Runs on the GL thread, drawing to the currently bound framebuffer.
+    pub fn draw(
+        &self,
+        gl: &Gl,
+        debug: bool,
+        width: i32,
+        height: i32,
+        time: f32,
+        frame: i32,
+    ) -> Result<(), GlError> {
+        let check = GlCheck::new(gl, debug);
+        gl.viewport(0, 0, width, height);
+        check.after("glViewport");
+        gl.use_program(self.program);
+        check.after("glUseProgram");
+        gl.uniform_1f(self.time, time);
+        gl.uniform_3f(self.resolution, width as f32, height as f32, 1.0);
+        gl.uniform_1i(self.frame, frame);
+        check.after("glUniform");
+        if let Some(vao) = self.vao {
+            gl.bind_vertex_array(vao);
+            check.after("glBindVertexArray");
+        }
+        gl.bind_buffer(gl::ARRAY_BUFFER, self.vbo);
+        check.after("glBindBuffer");
+        gl.vertex_attrib_pointer_f32(0, 2, false, 0, 0);
+        check.after("glVertexAttribPointer");
+        gl.enable_vertex_attrib_array(0);
+        check.after("glEnableVertexAttribArray");
+        gl.draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
+        check.after("glDrawArrays");
+        gl.disable_vertex_attrib_array(0);
+        gl.bind_buffer(gl::ARRAY_BUFFER, 0);
+        if self.vao.is_some() {
+            gl.bind_vertex_array(0);
+        }
+        gl.use_program(0);
+        check.finish("Drawing the fragment shader")
+    }
+
+    // Runs on the GL thread
+    pub fn delete(self, gl: &Gl) {
+        gl.delete_buffers(&[self.vbo]);
+        if let Some(vao) = self.vao {
+            gl.delete_vertex_arrays(&[vao]);
+        }
+        gl.delete_program(self.program);
+    }
+}
+
+pub fn compile(
+    gl: &Gl,
+    debug: bool,
+    shader_type: gl::GLenum,
+    source: &[&str],
+) -> Result<gl::GLuint, ShaderError> {
+    let check = GlCheck::new(gl, debug);
+    let shader = gl.create_shader(shader_type);
+    check.after("glCreateShader");
+    let source: Vec<&[u8]> = source.iter().map(|source| source.as_bytes()).collect();
+    gl.shader_source(shader, &source);
+    check.after("glShaderSource");
+    gl.compile_shader(shader);
+    check.after("glCompileShader");
+    if let Err(err) = check.finish("Compiling a shader") {
+        gl.delete_shader(shader);
+        return Err(ShaderError::Gl(err));
+    }
+    let mut status = [0];
+    unsafe { gl.get_shader_iv(shader, gl::COMPILE_STATUS, &mut status) };
+    if status[0] == 0 {
+        let log = gl.get_shader_info_log(shader);
+        gl.delete_shader(shader);
+        return Err(ShaderError::Compile(log));
+    }
+    Ok(shader)
+}
@@ -0,0 +1,273 @@
+use crate::pattern::Color;
+use crate::pattern::Paint;
+use crate::pattern::PatternParams;
+
+use png::ColorType;
+use png::Decoder;
+use png::DecodingError;
+use png::Transformations;
+
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+// A decoded still, with any alpha composited over black.
+pub struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+pub enum ImageError {
+    Io(io::Error),
+    Malformed(String),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImageError::Io(ref err) => write!(f, "{}", err),
+            ImageError::Malformed(ref reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> ImageError {
+        ImageError::Io(err)
+    }
+}
+
+impl From<DecodingError> for ImageError {
+    fn from(err: DecodingError) -> ImageError {
+        match err {
+            DecodingError::IoError(err) => ImageError::Io(err),
+            err => ImageError::Malformed(err.to_string()),
+        }
+    }
+}
+
+fn malformed<T>(reason: &str) -> Result<T, ImageError> {
+    Err(ImageError::Malformed(String::from(reason)))
+}
+
+impl Image {
+    // Loads a PNG or PPM file, going by its contents rather than its name.
+    pub fn load(path: &str) -> Result<Image, ImageError> {
+        let data = fs::read(path)?;
+        if data.starts_with(b"\x89PNG") {
+            Image::from_png(path)
+        } else if data.starts_with(b"P3") || data.starts_with(b"P6") {
+            Image::from_ppm(&data)
+        } else {
+            malformed("Not a PNG or PPM file")
+        }
+    }
+
+    fn from_png(path: &str) -> Result<Image, ImageError> {
+        let mut decoder = Decoder::new(File::open(path)?);
+        // Expand palettes and low bit depths, and drop to 8 bits per sample.
+        decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut data)?;
+
+        if info.color_type == ColorType::Indexed {
+            return malformed("Palette wasn't expanded");
+        }
+        let samples = info.color_type.samples();
+        let pixels = data
+            .chunks(info.line_size)
+            .take(info.height as usize)
+            .flat_map(|line| line.chunks_exact(samples).take(info.width as usize))
+            .map(|pixel| match info.color_type {
+                ColorType::Grayscale => Color::gray(pixel[0]),
+                ColorType::GrayscaleAlpha => Color::gray(over_black(pixel[0], pixel[1])),
+                ColorType::RGBA => Color::rgb(
+                    over_black(pixel[0], pixel[3]),
+                    over_black(pixel[1], pixel[3]),
+                    over_black(pixel[2], pixel[3]),
+                ),
+                _ => Color::rgb(pixel[0], pixel[1], pixel[2]),
+            })
+            .collect();
+        Image::new(info.width, info.height, pixels)
+    }
+
+    // The binary (P6) and plain (P3) flavours of PPM, with any maxval.
+    fn from_ppm(data: &[u8]) -> Result<Image, ImageError> {
+        let mut header = PpmTokens { data, position: 2 };
+        let width = header.number()?;
+        let height = header.number()?;
+        let maxval = header.number()?;
+        if maxval == 0 || maxval > 65535 {
+            return malformed("PPM maxval out of range");
+        }
+        let too_large = || ImageError::Malformed(String::from("PPM too large"));
+        let truncated = || ImageError::Malformed(String::from("PPM data truncated"));
+        let count = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|count| count.checked_mul(3))
+            .ok_or_else(too_large)?;
+        let scale = |value: u32| (value * 255 / maxval) as u8;
+
+        // The size comes from the header, so we check there's that much data
+        // before allocating anything.
+        let samples = if data.starts_with(b"P6") {
+            // Exactly one whitespace byte separates the header from the samples.
+            let start = header.position + 1;
+            let bytes = if maxval < 256 { 1 } else { 2 };
+            let end = count
+                .checked_mul(bytes)
+                .and_then(|len| len.checked_add(start))
+                .ok_or_else(too_large)?;
+            let body = data.get(start..end).ok_or_else(truncated)?;
+            let mut samples = Vec::with_capacity(count);
+            for sample in body.chunks_exact(bytes) {
+                let value = sample
+                    .iter()
+                    .fold(0, |value, byte| (value << 8) | u32::from(*byte));
+                if value > maxval {
+                    return malformed("PPM sample larger than maxval");
+                }
+                samples.push(scale(value));
+            }
+            samples
+        } else {
+            // Each sample takes at least a separator and a digit
+            if count > data.len().saturating_sub(header.position) / 2 {
+                return Err(truncated());
+            }
+            let mut samples = Vec::with_capacity(count);
+            for _ in 0..count {
+                let value = header.number()?;
+                if value > maxval {
+                    return malformed("PPM sample larger than maxval");
+                }
+                samples.push(scale(value));
+            }
+            samples
+        };
+
+        let pixels = samples
+            .chunks_exact(3)
+            .map(|pixel| Color::rgb(pixel[0], pixel[1], pixel[2]))
+            .collect();
+        Image::new(width, height, pixels)
+    }
+
+    fn new(width: u32, height: u32, pixels: Vec<Color>) -> Result<Image, ImageError> {
+        if width == 0 || height == 0 || pixels.len() != width as usize * height as usize {
+            return malformed("Image has no pixels");
+        }
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+// Images are scaled to the frame size with nearest-neighbour sampling.
+impl Paint for Image {
+    fn paint_line(&self, params: &PatternParams, y: u32, line: &mut [Color]) {
+        let image_y = (y as u64 * self.height as u64 / params.height.max(1) as u64) as usize;
+        let row = &self.pixels[image_y * self.width as usize..][..self.width as usize];
+        for (x, pixel) in line.iter_mut().enumerate() {
+            let image_x = x as u64 * self.width as u64 / params.width.max(1) as u64;
+            *pixel = row[image_x as usize];
+        }
+    }
+}
+
+fn over_black(value: u8, alpha: u8) -> u8 {
+    ((u32::from(value) * u32::from(alpha) + 127) / 255) as u8
+}
+
+// Whitespace separated decimal numbers, with # comments to the end of the line.
+struct PpmTokens<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PpmTokens<'a> {
+    fn number(&mut self) -> Result<u32, ImageError> {
+        loop {
+            match self.data.get(self.position) {
+                Some(b'#') => {
+                    while !matches!(self.data.get(self.position), Some(b'\n') | None) {
+                        self.position += 1;
+                    }
+                }
+                Some(byte) if byte.is_ascii_whitespace() => self.position += 1,
+                _ => break,
+            }
+        }
+        let start = self.position;
+        while matches!(self.data.get(self.position), Some(byte) if byte.is_ascii_digit()) {
+            self.position += 1;
+        }
+        std::str::from_utf8(&self.data[start..self.position])
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| ImageError::Malformed(String::from("Malformed PPM")))
+    }
+}
+
+// Expands a printf-style %d in the location, with optional zero padding and width.
+// A %% is a literal percent sign.
+pub fn format_location(location: &str, index: u64) -> String {
+    let mut result = String::new();
+    let mut chars = location.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        let mut spec = String::new();
+        while let Some(&c) = chars.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            spec.push(c);
+            chars.next();
+        }
+        match chars.next() {
+            Some('d') | Some('u') | Some('i') => {
+                let width = spec.parse().unwrap_or(0);
+                if spec.starts_with('0') {
+                    result.push_str(&format!("{:01$}", index, width));
+                } else {
+                    result.push_str(&format!("{:1$}", index, width));
+                }
+            }
+            Some('%') if spec.is_empty() => result.push('%'),
+            other => {
+                result.push('%');
+                result.push_str(&spec);
+                result.extend(other);
+            }
+        }
+    }
+    result
+}
+
+// The files making up a slideshow. A location with a %d is a numbered
+// sequence, starting at 0 or 1 and running until the first missing file.
+pub fn sequence_paths(location: &str) -> Vec<String> {
+    let path = format_location(location, 0);
+    if path == format_location(location, 1) {
+        return if Path::new(&path).exists() {
+            vec![path]
+        } else {
+            Vec::new()
+        };
+    }
+    let first = if Path::new(&path).exists() { 0 } else { 1 };
+    (first..)
+        .map(|index| format_location(location, index))
+        .take_while(|path| Path::new(path).exists())
+        .collect()
+}
@@ -1,12 +1,39 @@
 use glib::subclass::types::ObjectSubclass;
 use gstreamer::gst_plugin_define;
+use myaudiosrc::MyAudioSrc;
+use myavsyncsink::MyAvSyncSink;
+use mychecksumsink::MyChecksumSink;
+use myglfilter::MyGLFilter;
+pub use myglsrc::set_producer;
 use myglsrc::MyGLSrc;
+pub use myglsrc::SwapChainProducer;
+use mylatencysink::MyLatencySink;
+use mymixer::MyMixer;
 use mysrc::MySrc;
 use mytransform::MyTransform;
 
+mod barcode;
+mod clockwait;
+mod enums;
+mod formats;
+mod glconvert;
+mod glfilter;
+mod glreadback;
+mod glscene;
+mod glshader;
+mod image;
+mod myaudiosrc;
+mod myavsyncsink;
+mod mychecksumsink;
+mod myglfilter;
 mod myglsrc;
+mod mylatencysink;
+mod mymixer;
 mod mysrc;
 mod mytransform;
+mod pattern;
+mod sync;
+mod timing;
 
 gst_plugin_define!(
     myplugin,
@@ -39,5 +66,41 @@ fn plugin_init(plugin: &gstreamer::Plugin) -> Result<(), glib::BoolError> {
         gstreamer::Rank::None,
         MyGLSrc::get_type(),
     )?;
+    gstreamer::Element::register(
+        Some(plugin),
+        "myglfilter",
+        gstreamer::Rank::None,
+        MyGLFilter::get_type(),
+    )?;
+    gstreamer::Element::register(
+        Some(plugin),
+        "mychecksumsink",
+        gstreamer::Rank::None,
+        MyChecksumSink::get_type(),
+    )?;
+    gstreamer::Element::register(
+        Some(plugin),
+        "mymixer",
+        gstreamer::Rank::None,
+        MyMixer::get_type(),
+    )?;
+    gstreamer::Element::register(
+        Some(plugin),
+        "mylatencysink",
+        gstreamer::Rank::None,
+        MyLatencySink::get_type(),
+    )?;
+    gstreamer::Element::register(
+        Some(plugin),
+        "myaudiosrc",
+        gstreamer::Rank::None,
+        MyAudioSrc::get_type(),
+    )?;
+    gstreamer::Element::register(
+        Some(plugin),
+        "myavsyncsink",
+        gstreamer::Rank::None,
+        MyAvSyncSink::get_type(),
+    )?;
     Ok(())
 }
@@ -0,0 +1,334 @@
+use crate::clockwait::ClockWait;
+use crate::sync::in_beep;
+use crate::sync::sample_time;
+use crate::sync::AudioInfo;
+use crate::sync::BEEP_FREQUENCY;
+use crate::timing::skip_missed;
+
+use glib::glib_bool_error;
+use glib::glib_object_impl;
+use glib::glib_object_subclass;
+use glib::object::Cast;
+use glib::subclass::object::ObjectClassSubclassExt;
+use glib::subclass::object::ObjectImpl;
+use glib::subclass::object::ObjectImplExt;
+use glib::subclass::simple::ClassStruct;
+use glib::subclass::types::ObjectSubclass;
+use glib::subclass::Property;
+use glib::ParamFlags;
+use glib::ParamSpec;
+use glib::ToValue;
+use glib::Value;
+use gstreamer::gst_debug;
+use gstreamer::gst_element_error;
+use gstreamer::gst_info;
+use gstreamer::gst_loggable_error;
+use gstreamer::subclass::element::ElementClassSubclassExt;
+use gstreamer::subclass::element::ElementImpl;
+use gstreamer::subclass::ElementInstanceStruct;
+use gstreamer::BufferFlags;
+use gstreamer::BufferRef;
+use gstreamer::Caps;
+use gstreamer::ClockTime;
+use gstreamer::CoreError;
+use gstreamer::DebugCategory;
+use gstreamer::DebugColorFlags;
+use gstreamer::ErrorMessage;
+use gstreamer::FlowError;
+use gstreamer::FlowSuccess;
+use gstreamer::Format;
+use gstreamer::IntRange;
+use gstreamer::LoggableError;
+use gstreamer::PadDirection;
+use gstreamer::PadPresence;
+use gstreamer::PadTemplate;
+use gstreamer::QueryRef;
+use gstreamer::QueryView;
+use gstreamer::SECOND;
+use gstreamer_base::subclass::base_src::BaseSrcImpl;
+use gstreamer_base::subclass::base_src::BaseSrcImplExt;
+use gstreamer_base::BaseSrc;
+use gstreamer_base::BaseSrcExt;
+
+use std::f64::consts::PI;
+use std::sync::Mutex;
+
+const DEFAULT_RATE: i32 = 48000;
+const DEFAULT_CHANNELS: i32 = 1;
+const DEFAULT_SAMPLES_PER_BUFFER: u32 = 1024;
+
+// Beeps are at half volume, to leave headroom for mixing.
+const AMPLITUDE: f64 = 0.5 * i16::MAX as f64;
+
+static PROPERTIES: [Property; 2] = [
+    Property("samples-per-buffer", |name| {
+        ParamSpec::uint(
+            name,
+            "Samples per buffer",
+            "Number of samples in each outgoing buffer",
+            1,
+            u32::MAX,
+            DEFAULT_SAMPLES_PER_BUFFER,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("is-live", |name| {
+        ParamSpec::boolean(
+            name,
+            "Is live",
+            "Whether to act as a live source",
+            false,
+            ParamFlags::READWRITE,
+        )
+    }),
+];
+
+#[derive(Clone, Copy)]
+struct Settings {
+    samples_per_buffer: u32,
+    is_live: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            samples_per_buffer: DEFAULT_SAMPLES_PER_BUFFER,
+            is_live: false,
+        }
+    }
+}
+
+pub struct MyAudioSrc {
+    cat: DebugCategory,
+    next_sample: Mutex<u64>,
+    settings: Mutex<Settings>,
+    out_info: Mutex<Option<AudioInfo>>,
+    clock_wait: ClockWait,
+}
+
+impl ObjectSubclass for MyAudioSrc {
+    const NAME: &'static str = "MyAudioSrc";
+    type ParentType = BaseSrc;
+    type Instance = ElementInstanceStruct<Self>;
+    type Class = ClassStruct<Self>;
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "myaudiosrc",
+                DebugColorFlags::empty(),
+                Some("My audio src by me"),
+            ),
+            next_sample: Mutex::new(0),
+            settings: Mutex::new(Settings::default()),
+            out_info: Mutex::new(None),
+            clock_wait: ClockWait::default(),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "My Audio Src By Me",
+            "Source/Audio",
+            "Beeps at the start of every second, in time with mysrc's flashes",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+
+        let src_caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &"S16LE"),
+                ("layout", &"interleaved"),
+                ("rate", &IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &IntRange::<i32>::new(1, i32::MAX)),
+            ],
+        );
+        let src_pad_template =
+            PadTemplate::new("src", PadDirection::Src, PadPresence::Always, &src_caps).unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    glib_object_subclass!();
+}
+
+impl ObjectImpl for MyAudioSrc {
+    glib_object_impl!();
+
+    fn constructed(&self, obj: &glib::Object) {
+        self.parent_constructed(obj);
+        let basesrc = obj.downcast_ref::<BaseSrc>().unwrap();
+        basesrc.set_format(Format::Time);
+    }
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &Value) {
+        let mut settings = self.settings.lock().unwrap();
+        match PROPERTIES[id] {
+            Property("samples-per-buffer", ..) => {
+                settings.samples_per_buffer = value.get().unwrap()
+            }
+            Property("is-live", ..) => {
+                settings.is_live = value.get().unwrap();
+                let basesrc = obj.downcast_ref::<BaseSrc>().unwrap();
+                basesrc.set_live(settings.is_live);
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: usize) -> Result<Value, ()> {
+        let settings = self.settings.lock().unwrap();
+        match PROPERTIES[id] {
+            Property("samples-per-buffer", ..) => Ok(settings.samples_per_buffer.to_value()),
+            Property("is-live", ..) => Ok(settings.is_live.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for MyAudioSrc {}
+
+impl BaseSrcImpl for MyAudioSrc {
+    fn start(&self, src: &BaseSrc) -> Result<(), ErrorMessage> {
+        *self.next_sample.lock().unwrap() = 0;
+        gst_info!(self.cat, obj: src, "Started");
+        Ok(())
+    }
+
+    fn set_caps(&self, src: &BaseSrc, outcaps: &Caps) -> Result<(), LoggableError> {
+        let out_info = AudioInfo::from_caps(outcaps)
+            .ok_or_else(|| gst_loggable_error!(self.cat, "Failed to get audio info"))?;
+        gst_debug!(self.cat, obj: src, "Configured for caps {}", outcaps);
+        let samples_per_buffer = self.settings.lock().unwrap().samples_per_buffer;
+        src.set_blocksize(samples_per_buffer.saturating_mul(out_info.bytes_per_frame() as u32));
+        *self.out_info.lock().unwrap() = Some(out_info);
+        Ok(())
+    }
+
+    fn fixate(&self, src: &BaseSrc, caps: Caps) -> Caps {
+        let mut caps = Caps::truncate(caps);
+        {
+            let caps = caps.make_mut();
+            let s = caps.get_mut_structure(0).unwrap();
+            s.fixate_field_nearest_int("rate", DEFAULT_RATE);
+            s.fixate_field_nearest_int("channels", DEFAULT_CHANNELS);
+        }
+        gst_debug!(self.cat, obj: src, "Fixating caps {}", caps);
+        self.parent_fixate(src, caps)
+    }
+
+    fn query(&self, src: &BaseSrc, query: &mut QueryRef) -> bool {
+        if let QueryView::Latency(ref mut q) = query.view_mut() {
+            // A live source can't produce a buffer until the end of it has passed.
+            let settings = *self.settings.lock().unwrap();
+            let out_info = self.out_info.lock().unwrap();
+            if let Some(out_info) = out_info.as_ref() {
+                let latency = if settings.is_live {
+                    sample_time(out_info.rate, u64::from(settings.samples_per_buffer))
+                } else {
+                    0
+                };
+                let latency = ClockTime::from_nseconds(latency);
+                gst_debug!(self.cat, obj: src, "Returning latency {}", latency);
+                q.set(settings.is_live, latency, latency);
+                return true;
+            }
+            return false;
+        }
+        BaseSrcImplExt::parent_query(self, src, query)
+    }
+
+    fn unlock(&self, src: &BaseSrc) -> Result<(), ErrorMessage> {
+        gst_debug!(self.cat, obj: src, "Unlocking");
+        self.clock_wait.unlock();
+        Ok(())
+    }
+
+    fn unlock_stop(&self, src: &BaseSrc) -> Result<(), ErrorMessage> {
+        gst_debug!(self.cat, obj: src, "Unlock stop");
+        self.clock_wait.unlock_stop();
+        Ok(())
+    }
+
+    fn fill(
+        &self,
+        src: &BaseSrc,
+        _offset: u64,
+        _length: u32,
+        buffer: &mut BufferRef,
+    ) -> Result<FlowSuccess, FlowError> {
+        let out_info = self.out_info.lock().unwrap().ok_or_else(|| {
+            gst_element_error!(src, CoreError::Negotiation, ["Caps not set yet"]);
+            FlowError::NotNegotiated
+        })?;
+        let settings = *self.settings.lock().unwrap();
+        let samples = (buffer.get_size() / out_info.bytes_per_frame()) as u64;
+
+        // Like mysrc, everything is derived from the sample number.
+        let mut next_sample = self.next_sample.lock().unwrap();
+        let mut sample = *next_sample;
+        let mut discont = false;
+        if settings.is_live {
+            let skipped = skip_missed(self.cat, src, sample, samples, |now| {
+                let nanos = now.nseconds().unwrap_or(0) as u128;
+                (nanos * out_info.rate as u128 / SECOND.nseconds().unwrap() as u128) as u64
+            });
+            sample = skipped.0;
+            discont = skipped.1;
+        }
+        *next_sample = sample + samples;
+        drop(next_sample);
+
+        let pts = ClockTime::from_nseconds(sample_time(out_info.rate, sample));
+        let end = ClockTime::from_nseconds(sample_time(out_info.rate, sample + samples));
+        buffer.set_pts(pts);
+        buffer.set_duration(end - pts);
+        buffer.set_offset(sample);
+        buffer.set_offset_end(sample + samples);
+        if discont {
+            buffer.set_flags(BufferFlags::DISCONT);
+        }
+
+        {
+            let mut map = buffer.map_writable().ok_or_else(|| {
+                gst_element_error!(
+                    src,
+                    CoreError::Failed,
+                    ["Failed to map output buffer writable"]
+                );
+                FlowError::Error
+            })?;
+            let data = map.as_mut_slice();
+            for (index, frame) in data
+                .chunks_exact_mut(out_info.bytes_per_frame())
+                .enumerate()
+            {
+                let time = sample_time(out_info.rate, sample + index as u64);
+                let value = if in_beep(time) {
+                    let seconds = time as f64 / SECOND.nseconds().unwrap() as f64;
+                    (AMPLITUDE * (2.0 * PI * BEEP_FREQUENCY * seconds).sin()) as i16
+                } else {
+                    0
+                };
+                for channel in frame.chunks_exact_mut(2) {
+                    channel.copy_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        gst_debug!(
+            self.cat,
+            obj: src,
+            "Filled {} samples at {}",
+            samples,
+            pts
+        );
+
+        if settings.is_live {
+            let jitter = self.clock_wait.wait_until(src, end)?;
+            gst_debug!(self.cat, obj: src, "Waited with jitter {}", jitter);
+        }
+
+        Ok(FlowSuccess::Ok)
+    }
+}
@@ -0,0 +1,562 @@
+use crate::formats::read_luma;
+use crate::formats::FORMATS;
+use crate::sync::sample_time;
+use crate::sync::AudioInfo;
+use crate::sync::SYNC_INTERVAL;
+
+use glib::glib_object_impl;
+use glib::glib_object_subclass;
+use glib::object::Cast;
+use glib::subclass::object::ObjectImpl;
+use glib::subclass::object::ObjectImplExt;
+use glib::subclass::simple::ClassStruct;
+use glib::subclass::types::ObjectSubclass;
+use glib::value::ToSendValue;
+use gstreamer::gst_debug;
+use gstreamer::gst_element_error;
+use gstreamer::gst_info;
+use gstreamer::subclass::element::ElementClassSubclassExt;
+use gstreamer::subclass::element::ElementImpl;
+use gstreamer::subclass::element::ElementImplExt;
+use gstreamer::subclass::ElementInstanceStruct;
+use gstreamer::Buffer;
+use gstreamer::Caps;
+use gstreamer::ClockTime;
+use gstreamer::CoreError;
+use gstreamer::DebugCategory;
+use gstreamer::DebugColorFlags;
+use gstreamer::Element;
+use gstreamer::ElementExt;
+use gstreamer::ElementExtManual;
+use gstreamer::ElementFlags;
+use gstreamer::Event;
+use gstreamer::EventView;
+use gstreamer::FlowError;
+use gstreamer::FlowSuccess;
+use gstreamer::FormattedSegment;
+use gstreamer::Fraction;
+use gstreamer::FractionRange;
+use gstreamer::IntRange;
+use gstreamer::List;
+use gstreamer::Message;
+use gstreamer::Pad;
+use gstreamer::PadDirection;
+use gstreamer::PadExtManual;
+use gstreamer::PadPresence;
+use gstreamer::PadTemplate;
+use gstreamer::StateChange;
+use gstreamer::StateChangeError;
+use gstreamer::StateChangeReturn;
+use gstreamer::StateChangeSuccess;
+use gstreamer::StreamError;
+use gstreamer::Structure;
+use gstreamer_video::VideoFrameRef;
+use gstreamer_video::VideoInfo;
+
+use std::collections::VecDeque;
+use std::sync::Condvar;
+use std::sync::Mutex;
+
+// A frame is a flash if it is mostly white, and the one before wasn't.
+const FLASH_LUMA: u32 = 200;
+const FLASH_SAMPLES: u32 = 16;
+
+// A beep starts when the audio gets loud after at least 10ms of quiet.
+const BEEP_LEVEL: i16 = i16::MAX / 8;
+const QUIET_PER_SECOND: u64 = 100;
+
+#[derive(Default)]
+struct State {
+    video_info: Option<VideoInfo>,
+    audio_info: Option<AudioInfo>,
+    video_segment: FormattedSegment<ClockTime>,
+    audio_segment: FormattedSegment<ClockTime>,
+    flashing: bool,
+    quiet_samples: u64,
+    // Flashes and beeps waiting to be paired up, as running times in nanoseconds.
+    // Either stream can run ahead of the other, so there may be several of each.
+    flashes: VecDeque<u64>,
+    beeps: VecDeque<u64>,
+    // The running time each stream has been analysed up to.
+    video_position: u64,
+    audio_position: u64,
+    video_eos: bool,
+    audio_eos: bool,
+}
+
+impl State {
+    fn reset(&mut self) {
+        self.flashing = false;
+        self.quiet_samples = u64::MAX;
+        self.flashes.clear();
+        self.beeps.clear();
+        self.video_position = 0;
+        self.audio_position = 0;
+    }
+}
+
+// Like a BaseSink's preroll, but waiting for data on both pads.
+#[derive(Default)]
+struct Preroll {
+    // Whether the change to PAUSED is waiting for data.
+    pending: bool,
+    video: bool,
+    audio: bool,
+    playing: bool,
+    flushing: bool,
+}
+
+pub struct MyAvSyncSink {
+    cat: DebugCategory,
+    video_pad: Pad,
+    audio_pad: Pad,
+    state: Mutex<State>,
+    preroll: Mutex<Preroll>,
+    preroll_cond: Condvar,
+}
+
+impl ObjectSubclass for MyAvSyncSink {
+    const NAME: &'static str = "MyAvSyncSink";
+    type ParentType = Element;
+    type Instance = ElementInstanceStruct<Self>;
+    type Class = ClassStruct<Self>;
+
+    fn new_with_class(klass: &ClassStruct<Self>) -> Self {
+        let video_pad =
+            Pad::new_from_template(&klass.get_pad_template("video").unwrap(), Some("video"));
+        video_pad.set_chain_function(|pad, parent, buffer| {
+            MyAvSyncSink::catch_panic_pad_function(
+                parent,
+                || Err(FlowError::Error),
+                |this, element| this.video_chain(pad, element, buffer),
+            )
+        });
+        video_pad.set_event_function(|pad, parent, event| {
+            MyAvSyncSink::catch_panic_pad_function(
+                parent,
+                || false,
+                |this, element| this.sink_event(pad, element, event),
+            )
+        });
+
+        let audio_pad =
+            Pad::new_from_template(&klass.get_pad_template("audio").unwrap(), Some("audio"));
+        audio_pad.set_chain_function(|pad, parent, buffer| {
+            MyAvSyncSink::catch_panic_pad_function(
+                parent,
+                || Err(FlowError::Error),
+                |this, element| this.audio_chain(pad, element, buffer),
+            )
+        });
+        audio_pad.set_event_function(|pad, parent, event| {
+            MyAvSyncSink::catch_panic_pad_function(
+                parent,
+                || false,
+                |this, element| this.sink_event(pad, element, event),
+            )
+        });
+
+        let mut state = State::default();
+        state.reset();
+        Self {
+            cat: DebugCategory::new(
+                "myavsyncsink",
+                DebugColorFlags::empty(),
+                Some("My A/V sync sink by me"),
+            ),
+            video_pad,
+            audio_pad,
+            state: Mutex::new(state),
+            preroll: Mutex::new(Preroll::default()),
+            preroll_cond: Condvar::new(),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "My A/V Sync Sink By Me",
+            "Sink/Analyzer/Audio/Video",
+            "Measures the offset between mysrc's flashes and myaudiosrc's beeps",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+
+        let formats = FORMATS
+            .iter()
+            .map(|format| format.to_string())
+            .collect::<Vec<_>>();
+        let formats = formats
+            .iter()
+            .map(|format| format as &dyn ToSendValue)
+            .collect::<Vec<_>>();
+        let video_caps = Caps::new_simple(
+            "video/x-raw",
+            &[
+                ("format", &List::new(&formats)),
+                ("width", &IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &IntRange::<i32>::new(1, i32::MAX)),
+                (
+                    "framerate",
+                    &FractionRange::new(Fraction::new(0, 1), Fraction::new(i32::MAX, 1)),
+                ),
+            ],
+        );
+        let video_pad_template = PadTemplate::new(
+            "video",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &video_caps,
+        )
+        .unwrap();
+        klass.add_pad_template(video_pad_template);
+
+        let audio_caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &"S16LE"),
+                ("layout", &"interleaved"),
+                ("rate", &IntRange::<i32>::new(1, i32::MAX)),
+                ("channels", &IntRange::<i32>::new(1, i32::MAX)),
+            ],
+        );
+        let audio_pad_template = PadTemplate::new(
+            "audio",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &audio_caps,
+        )
+        .unwrap();
+        klass.add_pad_template(audio_pad_template);
+    }
+
+    glib_object_subclass!();
+}
+
+impl ObjectImpl for MyAvSyncSink {
+    glib_object_impl!();
+
+    fn constructed(&self, obj: &glib::Object) {
+        self.parent_constructed(obj);
+        let element = obj.downcast_ref::<Element>().unwrap();
+        element.add_pad(&self.video_pad).unwrap();
+        element.add_pad(&self.audio_pad).unwrap();
+        // So that bins wait for our EOS.
+        element.set_element_flags(ElementFlags::SINK);
+    }
+}
+
+impl ElementImpl for MyAvSyncSink {
+    fn change_state(
+        &self,
+        element: &Element,
+        transition: StateChange,
+    ) -> Result<StateChangeSuccess, StateChangeError> {
+        {
+            let mut preroll = self.preroll.lock().unwrap();
+            match transition {
+                StateChange::ReadyToPaused => {
+                    *preroll = Preroll::default();
+                    preroll.pending = true;
+                }
+                StateChange::PausedToPlaying => preroll.playing = true,
+                StateChange::PlayingToPaused => preroll.playing = false,
+                // Release any held buffers, so the pads can be deactivated
+                StateChange::PausedToReady => preroll.flushing = true,
+                _ => (),
+            }
+            self.preroll_cond.notify_all();
+        }
+        let success = self.parent_change_state(element, transition)?;
+        if transition == StateChange::ReadyToPaused {
+            // Like any sink, we can't be PAUSED until we have data
+            gst_debug!(self.cat, obj: element, "Waiting for preroll");
+            let message = Message::new_async_start().src(Some(element)).build();
+            let _ = element.post_message(&message);
+            return Ok(StateChangeSuccess::Async);
+        }
+        Ok(success)
+    }
+}
+
+impl MyAvSyncSink {
+    fn sink_event(&self, pad: &Pad, element: &Element, event: Event) -> bool {
+        let is_video = pad == &self.video_pad;
+        match event.view() {
+            EventView::FlushStart(..) => self.set_flushing(true),
+            EventView::FlushStop(..) => self.set_flushing(false),
+            EventView::Eos(..) => self.prerolled(element, is_video),
+            _ => (),
+        }
+        let mut state = self.state.lock().unwrap();
+        match event.view() {
+            EventView::Caps(caps) => {
+                let caps = caps.get_caps();
+                gst_debug!(self.cat, obj: pad, "Configured for caps {}", caps);
+                if is_video {
+                    state.video_info = VideoInfo::from_caps(caps);
+                    state.video_info.is_some()
+                } else {
+                    state.audio_info = AudioInfo::from_caps(caps);
+                    state.audio_info.is_some()
+                }
+            }
+            EventView::Segment(segment) => {
+                let segment = match segment.get_segment().downcast_ref::<ClockTime>() {
+                    Some(segment) => segment.clone(),
+                    None => {
+                        gst_element_error!(element, StreamError::Format, ["Segment not in time"]);
+                        return false;
+                    }
+                };
+                if is_video {
+                    state.video_segment = segment;
+                } else {
+                    state.audio_segment = segment;
+                }
+                true
+            }
+            EventView::FlushStop(..) => {
+                state.reset();
+                if is_video {
+                    state.video_eos = false;
+                } else {
+                    state.audio_eos = false;
+                }
+                true
+            }
+            EventView::Eos(..) => {
+                if is_video {
+                    state.video_eos = true;
+                } else {
+                    state.audio_eos = true;
+                }
+                // The other stream can't catch up any more
+                self.pair(element, &mut state);
+                if state.video_eos && state.audio_eos {
+                    gst_info!(self.cat, obj: element, "Both streams ended");
+                    drop(state);
+                    let message = Message::new_eos().src(Some(element)).build();
+                    let _ = element.post_message(&message);
+                }
+                true
+            }
+            _ => {
+                drop(state);
+                pad.event_default(Some(element), event)
+            }
+        }
+    }
+
+    fn video_chain(
+        &self,
+        pad: &Pad,
+        element: &Element,
+        buffer: Buffer,
+    ) -> Result<FlowSuccess, FlowError> {
+        self.preroll(element, true)?;
+        let mut state = self.state.lock().unwrap();
+        let info = state.video_info.as_ref().ok_or_else(|| {
+            gst_element_error!(element, CoreError::Negotiation, ["Caps not set yet"]);
+            FlowError::NotNegotiated
+        })?;
+        let frame =
+            VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), info).ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Failed to map input buffer readable"]
+                );
+                FlowError::Error
+            })?;
+
+        // Sample a grid of pixels rather than the whole frame.
+        let mut sum = 0;
+        let mut count = 0;
+        for row in 0..FLASH_SAMPLES {
+            for column in 0..FLASH_SAMPLES {
+                let x = (2 * column + 1) * frame.width() / (2 * FLASH_SAMPLES);
+                let y = (2 * row + 1) * frame.height() / (2 * FLASH_SAMPLES);
+                if let Some(luma) = read_luma(&frame, x, y) {
+                    sum += u32::from(luma);
+                    count += 1;
+                }
+            }
+        }
+        let flashing = count > 0 && sum >= count * FLASH_LUMA;
+        let starts = flashing && !state.flashing;
+        state.flashing = flashing;
+        let running_time = state.video_segment.to_running_time(buffer.get_pts());
+        if starts {
+            gst_debug!(self.cat, obj: pad, "Flash at {}", running_time);
+            state.flashes.extend(running_time.nseconds());
+        }
+        let end = buffer.get_pts() + buffer.get_duration();
+        let end = state.video_segment.to_running_time(end).nseconds();
+        if let Some(position) = end.or_else(|| running_time.nseconds()) {
+            state.video_position = state.video_position.max(position);
+        }
+        self.pair(element, &mut state);
+        Ok(FlowSuccess::Ok)
+    }
+
+    fn audio_chain(
+        &self,
+        pad: &Pad,
+        element: &Element,
+        buffer: Buffer,
+    ) -> Result<FlowSuccess, FlowError> {
+        self.preroll(element, false)?;
+        let mut state = self.state.lock().unwrap();
+        let info = state.audio_info.ok_or_else(|| {
+            gst_element_error!(element, CoreError::Negotiation, ["Caps not set yet"]);
+            FlowError::NotNegotiated
+        })?;
+        let map = buffer.map_readable().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Failed to map input buffer readable"]
+            );
+            FlowError::Error
+        })?;
+
+        // Only the first channel is listened to.
+        let quiet_needed = u64::from(info.rate) / QUIET_PER_SECOND;
+        let frames = map.as_slice().chunks_exact(info.bytes_per_frame());
+        let count = frames.len() as u64;
+        for (index, frame) in frames.enumerate() {
+            let sample = i16::from_le_bytes([frame[0], frame[1]]);
+            if sample.saturating_abs() < BEEP_LEVEL {
+                state.quiet_samples = state.quiet_samples.saturating_add(1);
+                continue;
+            }
+            if state.quiet_samples >= quiet_needed {
+                let offset = sample_time(info.rate, index as u64);
+                let pts = buffer.get_pts() + ClockTime::from_nseconds(offset);
+                let running_time = state.audio_segment.to_running_time(pts);
+                gst_debug!(self.cat, obj: pad, "Beep at {}", running_time);
+                state.beeps.extend(running_time.nseconds());
+            }
+            state.quiet_samples = 0;
+        }
+        let end = buffer.get_pts() + ClockTime::from_nseconds(sample_time(info.rate, count));
+        if let Some(position) = state.audio_segment.to_running_time(end).nseconds() {
+            state.audio_position = state.audio_position.max(position);
+        }
+        self.pair(element, &mut state);
+        Ok(FlowSuccess::Ok)
+    }
+
+    // Holds buffers while PAUSED, finishing the state change once both pads have data.
+    fn preroll(&self, element: &Element, is_video: bool) -> Result<(), FlowError> {
+        self.prerolled(element, is_video);
+        let mut preroll = self.preroll.lock().unwrap();
+        while !preroll.playing && !preroll.flushing {
+            preroll = self.preroll_cond.wait(preroll).unwrap();
+        }
+        if preroll.flushing {
+            return Err(FlowError::Flushing);
+        }
+        Ok(())
+    }
+
+    // Notes that a pad has data or is EOS, completing the change to PAUSED if both are.
+    fn prerolled(&self, element: &Element, is_video: bool) {
+        {
+            let mut preroll = self.preroll.lock().unwrap();
+            if is_video {
+                preroll.video = true;
+            } else {
+                preroll.audio = true;
+            }
+            if !preroll.pending || !preroll.video || !preroll.audio {
+                return;
+            }
+            preroll.pending = false;
+        }
+        // Not holding the lock, since this may go on to PLAYING
+        gst_info!(self.cat, obj: element, "Prerolled");
+        let _ = element.continue_state(StateChangeReturn::Success);
+        let message = Message::new_async_done(ClockTime::none())
+            .src(Some(element))
+            .build();
+        let _ = element.post_message(&message);
+    }
+
+    fn set_flushing(&self, flushing: bool) {
+        let mut preroll = self.preroll.lock().unwrap();
+        preroll.flushing = flushing;
+        if !flushing {
+            preroll.video = false;
+            preroll.audio = false;
+        }
+        self.preroll_cond.notify_all();
+    }
+
+    // Pairs each flash with the nearest beep, once the audio has got far enough that
+    // no nearer beep can arrive. Events are only dropped when neither stream can
+    // have a partner for them any more.
+    fn pair(&self, element: &Element, state: &mut State) {
+        let half = SYNC_INTERVAL / 2;
+        let video_position = if state.video_eos {
+            u64::MAX
+        } else {
+            state.video_position
+        };
+        let audio_position = if state.audio_eos {
+            u64::MAX
+        } else {
+            state.audio_position
+        };
+        loop {
+            // Flashes arrive in order, so a beep too early for the next one has no partner
+            let next_flash = state.flashes.front().copied();
+            let too_early = next_flash.unwrap_or(video_position);
+            while let Some(&beep) = state.beeps.front() {
+                if beep.saturating_add(half) > too_early {
+                    break;
+                }
+                gst_debug!(self.cat, obj: element, "Beep at {} has no flash", beep);
+                state.beeps.pop_front();
+            }
+            let flash = match next_flash {
+                Some(flash) if flash.saturating_add(half) <= audio_position => flash,
+                _ => return,
+            };
+            state.flashes.pop_front();
+            let nearest = state
+                .beeps
+                .iter()
+                .enumerate()
+                .filter(|&(_, &beep)| beep < flash.saturating_add(half))
+                .min_by_key(|&(_, &beep)| (beep as i64 - flash as i64).unsigned_abs())
+                .map(|(index, &beep)| (index, beep));
+            match nearest {
+                Some((index, beep)) => {
+                    state.beeps.remove(index);
+                    self.report(element, flash, beep);
+                }
+                None => gst_debug!(self.cat, obj: element, "Flash at {} has no beep", flash),
+            }
+        }
+    }
+
+    fn report(&self, element: &Element, flash: u64, beep: u64) {
+        let offset = beep as i64 - flash as i64;
+        let flash = ClockTime::from_nseconds(flash);
+        let beep = ClockTime::from_nseconds(beep);
+        gst_info!(
+            self.cat,
+            obj: element,
+            "Audio is {}ns after video at {}",
+            offset,
+            flash
+        );
+        let structure = Structure::builder("myavsyncsink")
+            .field("video-running-time", &flash)
+            .field("audio-running-time", &beep)
+            .field("offset", &offset)
+            .build();
+        let message = Message::new_element(structure).src(Some(element)).build();
+        let _ = element.post_message(&message);
+    }
+}
@@ -0,0 +1,389 @@
+use glib::glib_bool_error;
+use glib::glib_object_impl;
+use glib::glib_object_subclass;
+use glib::subclass::object::ObjectClassSubclassExt;
+use glib::subclass::object::ObjectImpl;
+use glib::subclass::simple::ClassStruct;
+use glib::subclass::types::ObjectSubclass;
+use glib::subclass::Property;
+use glib::ParamFlags;
+use glib::ParamSpec;
+use glib::ToValue;
+use glib::Value;
+use gstreamer::gst_debug;
+use gstreamer::gst_element_error;
+use gstreamer::gst_error_msg;
+use gstreamer::gst_info;
+use gstreamer::gst_loggable_error;
+use gstreamer::subclass::element::ElementClassSubclassExt;
+use gstreamer::subclass::element::ElementImpl;
+use gstreamer::subclass::ElementInstanceStruct;
+use gstreamer::Buffer;
+use gstreamer::Caps;
+use gstreamer::ClockTime;
+use gstreamer::CoreError;
+use gstreamer::DebugCategory;
+use gstreamer::DebugColorFlags;
+use gstreamer::ErrorMessage;
+use gstreamer::Event;
+use gstreamer::EventView;
+use gstreamer::FlowError;
+use gstreamer::FlowSuccess;
+use gstreamer::LoggableError;
+use gstreamer::PadDirection;
+use gstreamer::PadPresence;
+use gstreamer::PadTemplate;
+use gstreamer::ResourceError;
+use gstreamer::StreamError;
+use gstreamer_base::subclass::base_sink::BaseSinkImpl;
+use gstreamer_base::subclass::base_sink::BaseSinkImplExt;
+use gstreamer_base::BaseSink;
+use gstreamer_video::VideoFrameRef;
+use gstreamer_video::VideoInfo;
+
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::sync::Mutex;
+
+static PROPERTIES: [Property; 2] = [
+    Property("location", |name| {
+        ParamSpec::string(
+            name,
+            "Location",
+            "File to write the per-frame checksum log to",
+            None,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("reference-location", |name| {
+        ParamSpec::string(
+            name,
+            "Reference location",
+            "Previously recorded checksum log to compare frames against",
+            None,
+            ParamFlags::READWRITE,
+        )
+    }),
+];
+
+#[derive(Default)]
+struct Settings {
+    location: Option<String>,
+    reference_location: Option<String>,
+}
+
+struct State {
+    info: Option<VideoInfo>,
+    frames: u64,
+    log: Option<BufWriter<File>>,
+    reference: Option<Vec<u64>>,
+}
+
+pub struct MyChecksumSink {
+    cat: DebugCategory,
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+impl ObjectSubclass for MyChecksumSink {
+    const NAME: &'static str = "MyChecksumSink";
+    type ParentType = BaseSink;
+    type Instance = ElementInstanceStruct<Self>;
+    type Class = ClassStruct<Self>;
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "mychecksumsink",
+                DebugColorFlags::empty(),
+                Some("My checksum sink by me"),
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "My Checksum Sink By Me",
+            "Sink/Video",
+            "Hashes the visible pixels of each frame",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+
+        let sink_caps = Caps::new_simple("video/x-raw", &[]);
+        let sink_pad_template =
+            PadTemplate::new("sink", PadDirection::Sink, PadPresence::Always, &sink_caps).unwrap();
+        klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+
+    glib_object_subclass!();
+}
+
+impl ObjectImpl for MyChecksumSink {
+    glib_object_impl!();
+
+    fn set_property(&self, _obj: &glib::Object, id: usize, value: &Value) {
+        let mut settings = self.settings.lock().unwrap();
+        match PROPERTIES[id] {
+            Property("location", ..) => settings.location = value.get(),
+            Property("reference-location", ..) => settings.reference_location = value.get(),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: usize) -> Result<Value, ()> {
+        let settings = self.settings.lock().unwrap();
+        match PROPERTIES[id] {
+            Property("location", ..) => Ok(settings.location.to_value()),
+            Property("reference-location", ..) => Ok(settings.reference_location.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for MyChecksumSink {}
+
+impl BaseSinkImpl for MyChecksumSink {
+    fn start(&self, sink: &BaseSink) -> Result<(), ErrorMessage> {
+        let settings = self.settings.lock().unwrap();
+
+        let log = match settings.location {
+            None => None,
+            Some(ref location) => {
+                let file = File::create(location).map_err(|err| {
+                    gst_error_msg!(
+                        ResourceError::OpenWrite,
+                        ["Failed to create {}: {}", location, err]
+                    )
+                })?;
+                Some(BufWriter::new(file))
+            }
+        };
+
+        let reference = match settings.reference_location {
+            None => None,
+            Some(ref location) => Some(read_checksum_log(location)?),
+        };
+
+        *self.state.lock().unwrap() = Some(State {
+            info: None,
+            frames: 0,
+            log,
+            reference,
+        });
+        gst_info!(self.cat, obj: sink, "Started");
+        Ok(())
+    }
+
+    fn stop(&self, sink: &BaseSink) -> Result<(), ErrorMessage> {
+        if let Some(mut state) = self.state.lock().unwrap().take() {
+            if let Some(ref mut log) = state.log {
+                log.flush().map_err(|err| {
+                    gst_error_msg!(ResourceError::Write, ["Failed to flush log: {}", err])
+                })?;
+            }
+        }
+        gst_info!(self.cat, obj: sink, "Stopped");
+        Ok(())
+    }
+
+    fn set_caps(&self, sink: &BaseSink, caps: &Caps) -> Result<(), LoggableError> {
+        let info = VideoInfo::from_caps(caps)
+            .ok_or_else(|| gst_loggable_error!(self.cat, "Failed to get video info"))?;
+        gst_debug!(self.cat, obj: sink, "Configured for caps {}", caps);
+        let mut state_guard = self.state.lock().unwrap();
+        let state = state_guard
+            .as_mut()
+            .ok_or_else(|| gst_loggable_error!(self.cat, "Not started yet"))?;
+        state.info = Some(info);
+        Ok(())
+    }
+
+    fn event(&self, sink: &BaseSink, event: Event) -> bool {
+        if let EventView::Eos(..) = event.view() {
+            let state_guard = self.state.lock().unwrap();
+            if let Some(ref state) = *state_guard {
+                if let Some(ref reference) = state.reference {
+                    if state.frames < reference.len() as u64 {
+                        gst_element_error!(
+                            sink,
+                            StreamError::Failed,
+                            ("Stream ended after {} frames", state.frames),
+                            ["Reference log has {} frames", reference.len()]
+                        );
+                    }
+                }
+            }
+        }
+        self.parent_event(sink, event)
+    }
+
+    fn render(&self, sink: &BaseSink, buffer: &Buffer) -> Result<FlowSuccess, FlowError> {
+        let mut state_guard = self.state.lock().unwrap();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst_element_error!(sink, CoreError::Negotiation, ["Have no state yet"]);
+            FlowError::NotNegotiated
+        })?;
+        let info = state.info.as_ref().ok_or_else(|| {
+            gst_element_error!(sink, CoreError::Negotiation, ["Caps not set yet"]);
+            FlowError::NotNegotiated
+        })?;
+
+        let frame =
+            VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), info).ok_or_else(|| {
+                gst_element_error!(
+                    sink,
+                    CoreError::Failed,
+                    ["Failed to map input buffer readable"]
+                );
+                FlowError::Error
+            })?;
+        let checksum = frame_checksum(&frame);
+        let index = state.frames;
+        state.frames += 1;
+        gst_debug!(
+            self.cat,
+            obj: sink,
+            "Frame {} at {} has checksum {:016x}",
+            index,
+            buffer.get_pts(),
+            checksum
+        );
+
+        if let Some(ref mut log) = state.log {
+            writeln!(
+                log,
+                "{} {} {:016x}",
+                index,
+                pts_string(buffer.get_pts()),
+                checksum
+            )
+            .map_err(|err| {
+                gst_element_error!(sink, ResourceError::Write, ["Failed to write log: {}", err]);
+                FlowError::Error
+            })?;
+        }
+
+        if let Some(ref reference) = state.reference {
+            match reference.get(index as usize) {
+                Some(&expected) if expected == checksum => (),
+                Some(&expected) => {
+                    gst_element_error!(
+                        sink,
+                        StreamError::Failed,
+                        ("Checksum mismatch at frame {}", index),
+                        [
+                            "Expected {:016x}, got {:016x} at {}",
+                            expected,
+                            checksum,
+                            buffer.get_pts()
+                        ]
+                    );
+                    return Err(FlowError::Error);
+                }
+                None => {
+                    gst_element_error!(
+                        sink,
+                        StreamError::Failed,
+                        ("Checksum mismatch at frame {}", index),
+                        ["Reference log has only {} frames", reference.len()]
+                    );
+                    return Err(FlowError::Error);
+                }
+            }
+        }
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+// The log has one line per frame: the frame number, the PTS in nanoseconds
+// (or "none"), and the checksum in hex.
+fn read_checksum_log(location: &str) -> Result<Vec<u64>, ErrorMessage> {
+    let file = File::open(location).map_err(|err| {
+        gst_error_msg!(
+            ResourceError::OpenRead,
+            ["Failed to open {}: {}", location, err]
+        )
+    })?;
+    let mut checksums = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| {
+            gst_error_msg!(
+                ResourceError::Read,
+                ["Failed to read {}: {}", location, err]
+            )
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let checksum = line
+            .split_whitespace()
+            .nth(2)
+            .and_then(|checksum| u64::from_str_radix(checksum, 16).ok())
+            .ok_or_else(|| {
+                gst_error_msg!(
+                    StreamError::Format,
+                    ["Malformed line {} in {}", number + 1, location]
+                )
+            })?;
+        checksums.push(checksum);
+    }
+    Ok(checksums)
+}
+
+fn pts_string(pts: ClockTime) -> String {
+    match pts.nseconds() {
+        Some(nseconds) => nseconds.to_string(),
+        None => String::from("none"),
+    }
+}
+
+// FNV-1a over the visible bytes of each plane, so that the checksum
+// doesn't depend on stride padding or on the memory layout of the buffer.
+fn frame_checksum(frame: &VideoFrameRef<&gstreamer::BufferRef>) -> u64 {
+    const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let format_info = frame.format_info();
+    let width = frame.width();
+    let height = frame.height();
+    let mut hash = FNV_OFFSET;
+
+    for plane in 0..frame.n_planes() {
+        let stride = frame.plane_stride()[plane as usize] as usize;
+        let data = frame.plane_data(plane).unwrap_or(&[]);
+
+        // The visible part of a plane is the widest and tallest of its components.
+        let mut line_bytes = 0;
+        let mut lines = 0;
+        for component in 0..format_info.n_components() as usize {
+            if format_info.plane()[component] != plane {
+                continue;
+            }
+            let pixel_stride = format_info.pixel_stride()[component] as usize;
+            let component_width = format_info.scale_width(component as u8, width) as usize;
+            let component_height = format_info.scale_height(component as u8, height) as usize;
+            line_bytes = line_bytes.max(pixel_stride * component_width);
+            lines = lines.max(component_height);
+        }
+        if line_bytes == 0 || line_bytes > stride {
+            line_bytes = stride;
+        }
+
+        for line in data.chunks(stride).take(lines) {
+            for byte in &line[..line_bytes.min(line.len())] {
+                hash ^= u64::from(*byte);
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        }
+    }
+
+    hash
+}
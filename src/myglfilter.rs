@@ -0,0 +1,276 @@
+use crate::glfilter::render_to_target;
+use crate::glfilter::GLFilter;
+use crate::glshader::texture_function;
+use crate::glshader::FragmentShader;
+use crate::glshader::GlCheck;
+
+use sparkle::gl;
+use sparkle::gl::Gl;
+
+use glib::glib_object_impl;
+use glib::glib_object_subclass;
+use glib::glib_sys::gboolean;
+use glib::glib_sys::GFALSE;
+use glib::glib_sys::GTRUE;
+use glib::subclass::object::ObjectClassSubclassExt;
+use glib::subclass::object::ObjectImpl;
+use glib::subclass::simple::ClassStruct;
+use glib::subclass::types::InstanceStruct;
+use glib::subclass::types::ObjectSubclass;
+use glib::subclass::Property;
+use glib::translate::from_glib_borrow;
+use glib::translate::from_glib_none;
+use glib::ParamFlags;
+use glib::ParamSpec;
+use glib::ToValue;
+use glib::Value;
+use gstreamer::gst_element_error;
+use gstreamer::gst_info;
+use gstreamer::subclass::element::ElementClassSubclassExt;
+use gstreamer::subclass::element::ElementImpl;
+use gstreamer::subclass::ElementInstanceStruct;
+use gstreamer::CoreError;
+use gstreamer::DebugCategory;
+use gstreamer::DebugColorFlags;
+use gstreamer::LibraryError;
+use gstreamer_gl::GLContext;
+use gstreamer_gl::GLContextExt;
+use gstreamer_gl::GLContextExtManual;
+use gstreamer_gl::GLAPI;
+use gstreamer_gl_sys::gst_gl_filter_add_rgba_pad_templates;
+use gstreamer_gl_sys::GstGLBaseFilter;
+use gstreamer_gl_sys::GstGLBaseFilterClass;
+use gstreamer_gl_sys::GstGLFilter;
+use gstreamer_gl_sys::GstGLFilterClass;
+use gstreamer_gl_sys::GstGLMemory;
+
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+// The same tint as mytransform, which halves every channel and adds 127 to green,
+// keeping alpha. The arithmetic is on bytes so it rounds the same way.
+const TINT: &str = "vec4 tint(vec4 rgba) {
+    vec3 c = floor(floor(rgba.rgb * 255.0 + 0.5) / 2.0) + vec3(0.0, 127.0, 0.0);
+    return vec4(c / 255.0, rgba.a);
+}
+";
+
+static PROPERTIES: [Property; 1] = [Property("gl-debug", |name| {
+    ParamSpec::boolean(
+        name,
+        "GL debug",
+        "Check every GL call for errors",
+        false,
+        ParamFlags::READWRITE,
+    )
+})];
+
+struct GlResources {
+    gl: Rc<Gl>,
+    shader: FragmentShader,
+}
+
+// The GL bindings aren't Send, but we only use them on the GL thread.
+unsafe impl Send for GlResources {}
+
+pub struct MyGLFilter {
+    cat: DebugCategory,
+    gl_debug: AtomicBool,
+    gl_resources: Mutex<Option<GlResources>>,
+}
+
+impl ObjectSubclass for MyGLFilter {
+    const NAME: &'static str = "MyGLFilter";
+    type ParentType = GLFilter;
+    type Instance = ElementInstanceStruct<Self>;
+    type Class = ClassStruct<Self>;
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "myglfilter",
+                DebugColorFlags::empty(),
+                Some("My GL filter by me"),
+            ),
+            gl_debug: AtomicBool::new(false),
+            gl_resources: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "My GLFilter By Me",
+            "Filter/Effect/Video",
+            "Does the same stuff as mytransform, on the GPU",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+
+        klass.install_properties(&PROPERTIES);
+
+        // GLFilterImpl doesn't exist, so we override the vfuncs ourselves
+        unsafe {
+            let gl_filter_class = klass as *mut ClassStruct<Self> as *mut GstGLFilterClass;
+            gst_gl_filter_add_rgba_pad_templates(gl_filter_class);
+            (*gl_filter_class).filter_texture = Some(filter_texture_trampoline);
+            let gl_base_filter_class = gl_filter_class as *mut GstGLBaseFilterClass;
+            (*gl_base_filter_class).gl_start = Some(gl_start_trampoline);
+            (*gl_base_filter_class).gl_stop = Some(gl_stop_trampoline);
+        }
+    }
+
+    glib_object_subclass!();
+}
+
+impl ObjectImpl for MyGLFilter {
+    glib_object_impl!();
+
+    fn set_property(&self, _obj: &glib::Object, id: usize, value: &Value) {
+        match PROPERTIES[id] {
+            Property("gl-debug", ..) => self.gl_debug.store(value.get().unwrap(), Ordering::SeqCst),
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: usize) -> Result<Value, ()> {
+        match PROPERTIES[id] {
+            Property("gl-debug", ..) => Ok(self.gl_debug.load(Ordering::SeqCst).to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for MyGLFilter {}
+
+impl MyGLFilter {
+    // Runs on the GL thread
+    fn gl_start(&self, filter: &GLFilter, gl_context: &GLContext) -> bool {
+        let gl = Gl::gl_fns(gl::ffi_gl::Gl::load_with(|s| {
+            gl_context.get_proc_address(s) as *const _
+        }));
+        let debug = self.gl_debug.load(Ordering::SeqCst);
+        let api = gl_context.get_gl_api();
+        let shader = match FragmentShader::new(&gl, api, &tint_source(api), debug) {
+            Ok(shader) => shader,
+            Err(err) => {
+                gst_element_error!(
+                    filter,
+                    LibraryError::Init,
+                    ["Failed to create the tint shader: {}", err]
+                );
+                return false;
+            }
+        };
+        gst_info!(self.cat, obj: filter, "Started");
+        *self.gl_resources.lock().unwrap() = Some(GlResources { gl, shader });
+        true
+    }
+
+    // Runs on the GL thread
+    fn gl_stop(&self, filter: &GLFilter) {
+        if let Some(resources) = self.gl_resources.lock().unwrap().take() {
+            resources.shader.delete(&resources.gl);
+        }
+        gst_info!(self.cat, obj: filter, "Stopped");
+    }
+
+    fn filter_texture(
+        &self,
+        filter: &GLFilter,
+        input: &GstGLMemory,
+        output: &GstGLMemory,
+        width: i32,
+        height: i32,
+    ) -> bool {
+        let debug = self.gl_debug.load(Ordering::SeqCst);
+        render_to_target(filter, input, output, |input| {
+            let resources = self.gl_resources.lock().unwrap();
+            let resources = match resources.as_ref() {
+                Some(resources) => resources,
+                None => {
+                    gst_element_error!(filter, CoreError::Failed, ["Have no GL resources"]);
+                    return false;
+                }
+            };
+            let gl = &resources.gl;
+            let check = GlCheck::new(gl, debug);
+            gl.active_texture(gl::TEXTURE0);
+            gl.bind_texture(gl::TEXTURE_2D, input.tex_id);
+            check.after("glBindTexture");
+            let result = check
+                .finish("Binding the input texture")
+                .and_then(|_| resources.shader.draw(gl, debug, width, height, 0.0, 0));
+            gl.bind_texture(gl::TEXTURE_2D, 0);
+            if let Err(err) = result {
+                gst_element_error!(filter, LibraryError::Failed, ["{}", err]);
+                return false;
+            }
+            true
+        })
+    }
+}
+
+unsafe extern "C" fn gl_start_trampoline(ptr: *mut GstGLBaseFilter) -> gboolean {
+    let instance = &*(ptr as *mut ElementInstanceStruct<MyGLFilter>);
+    let imp = instance.get_impl();
+    let filter: GLFilter = from_glib_borrow(ptr as *mut GstGLFilter);
+    let gl_context: GLContext = from_glib_none((*ptr).context);
+    // Let GLFilter set up its framebuffer
+    let parent_class =
+        MyGLFilter::type_data().as_ref().get_parent_class() as *mut GstGLBaseFilterClass;
+    if let Some(f) = (*parent_class).gl_start {
+        if f(ptr) == GFALSE {
+            return GFALSE;
+        }
+    }
+    if imp.gl_start(&filter, &gl_context) {
+        GTRUE
+    } else {
+        GFALSE
+    }
+}
+
+unsafe extern "C" fn gl_stop_trampoline(ptr: *mut GstGLBaseFilter) {
+    let instance = &*(ptr as *mut ElementInstanceStruct<MyGLFilter>);
+    let imp = instance.get_impl();
+    let filter: GLFilter = from_glib_borrow(ptr as *mut GstGLFilter);
+    imp.gl_stop(&filter);
+    let parent_class =
+        MyGLFilter::type_data().as_ref().get_parent_class() as *mut GstGLBaseFilterClass;
+    if let Some(f) = (*parent_class).gl_stop {
+        f(ptr);
+    }
+}
+
+unsafe extern "C" fn filter_texture_trampoline(
+    ptr: *mut GstGLFilter,
+    input: *mut GstGLMemory,
+    output: *mut GstGLMemory,
+) -> gboolean {
+    let instance = &*(ptr as *mut ElementInstanceStruct<MyGLFilter>);
+    let imp = instance.get_impl();
+    let filter: GLFilter = from_glib_borrow(ptr);
+    let width = (*ptr).out_info.width;
+    let height = (*ptr).out_info.height;
+    if imp.filter_texture(&filter, &*input, &*output, width, height) {
+        GTRUE
+    } else {
+        GFALSE
+    }
+}
+
+// The pad templates only allow RGBA in 2D textures, so the input is sampled
+// where each output pixel is, scaling it if the sizes differ.
+fn tint_source(api: GLAPI) -> String {
+    format!(
+        "uniform sampler2D myglfilter_input;
+{}
+void mainImage(out vec4 fragColor, in vec2 fragCoord) {{
+    fragColor = tint({}(myglfilter_input, fragCoord / iResolution.xy));
+}}
+",
+        TINT,
+        texture_function(api)
+    )
+}
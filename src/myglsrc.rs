@@ -1,75 +1,338 @@
+use crate::clockwait::ClockWait;
+use crate::glconvert::Converter;
+use crate::glreadback::Pixels;
+use crate::glreadback::Readback;
+use crate::glscene::Camera;
+use crate::glscene::Mesh;
+use crate::glscene::Scene;
+use crate::glscene::SceneRenderer;
+use crate::glshader::check_error;
+use crate::glshader::FragmentShader;
+use crate::glshader::GlCheck;
+use crate::glshader::GlError;
+use crate::timing::frame_at;
+use crate::timing::frame_time;
+use crate::timing::skip_missed;
+
+use euclid::default::Size2D;
 use sparkle::gl;
 use sparkle::gl::Gl;
+use surfman::platform::generic::universal::context::Context as SurfmanContext;
+use surfman::platform::generic::universal::device::Device;
+use surfman::platform::generic::universal::surface::Surface;
+use surfman_chains::SwapChain;
 
 use glib::glib_bool_error;
 use glib::glib_object_impl;
 use glib::glib_object_subclass;
+use glib::glib_sys::gboolean;
+use glib::glib_sys::GFALSE;
+use glib::glib_sys::GTRUE;
 use glib::object::Cast;
 use glib::object::ObjectType;
+use glib::subclass::object::ObjectClassSubclassExt;
 use glib::subclass::object::ObjectImpl;
 use glib::subclass::object::ObjectImplExt;
 use glib::subclass::simple::ClassStruct;
+use glib::subclass::types::InstanceStruct;
 use glib::subclass::types::ObjectSubclass;
+use glib::subclass::Property;
+use glib::translate::from_glib;
+use glib::translate::from_glib_borrow;
+use glib::translate::from_glib_full;
 use glib::translate::FromGlibPtrBorrow;
+use glib::translate::ToGlib;
+use glib::translate::ToGlibPtr;
+use glib::ObjectExt;
+use glib::ParamFlags;
+use glib::ParamSpec;
+use glib::SignalFlags;
+use glib::StaticType;
+use glib::ToValue;
+use glib::Type;
+use glib::Value;
+use gobject_sys::g_signal_has_handler_pending;
+use gobject_sys::g_signal_lookup;
+use gobject_sys::GObject;
 use gstreamer::gst_debug;
 use gstreamer::gst_element_error;
+use gstreamer::gst_error;
 use gstreamer::gst_info;
+use gstreamer::gst_log;
 use gstreamer::gst_loggable_error;
+use gstreamer::gst_warning;
 use gstreamer::subclass::element::ElementClassSubclassExt;
 use gstreamer::subclass::element::ElementImpl;
+use gstreamer::subclass::element::ElementImplExt;
 use gstreamer::subclass::ElementInstanceStruct;
 use gstreamer::Buffer;
+use gstreamer::BufferFlags;
 use gstreamer::BufferPool;
 use gstreamer::BufferPoolExt;
 use gstreamer::BufferPoolExtManual;
 use gstreamer::Caps;
+use gstreamer::CapsFeatures;
+use gstreamer::ClockTime;
+use gstreamer::Context;
 use gstreamer::CoreError;
 use gstreamer::DebugCategory;
 use gstreamer::DebugColorFlags;
 use gstreamer::Element;
+use gstreamer::ErrorMessage;
 use gstreamer::FlowError;
 use gstreamer::Format;
 use gstreamer::Fraction;
+use gstreamer::GstObjectExt;
+use gstreamer::LibraryError;
 use gstreamer::LoggableError;
+use gstreamer::MiniObject;
 use gstreamer::PadDirection;
 use gstreamer::PadPresence;
 use gstreamer::PadTemplate;
+use gstreamer::QueryRef;
+use gstreamer::QueryView;
+use gstreamer::ResourceError;
+use gstreamer::StreamError;
 use gstreamer_base::subclass::base_src::BaseSrcImpl;
+use gstreamer_base::subclass::base_src::BaseSrcImplExt;
 use gstreamer_base::BaseSrc;
 use gstreamer_base::BaseSrcExt;
+use gstreamer_base_sys::GstBaseSrc;
+use gstreamer_base_sys::GstBaseSrcClass;
 use gstreamer_gl::GLContext;
 use gstreamer_gl::GLContextExt;
 use gstreamer_gl::GLContextExtManual;
+use gstreamer_gl::GLDisplay;
+#[cfg(target_os = "linux")]
+use gstreamer_gl::GLDisplayEGL;
+use gstreamer_gl::GLSyncMeta;
+use gstreamer_gl::GLAPI;
+use gstreamer_gl_sys::gst_gl_buffer_pool_get_type;
+use gstreamer_gl_sys::gst_gl_buffer_pool_new;
 use gstreamer_gl_sys::gst_gl_context_thread_add;
+use gstreamer_gl_sys::gst_gl_ensure_element_data;
+use gstreamer_gl_sys::gst_gl_handle_context_query;
+use gstreamer_gl_sys::gst_gl_handle_set_context;
+use gstreamer_gl_sys::gst_gl_memory_get_texture_height;
+use gstreamer_gl_sys::gst_gl_memory_get_texture_width;
+use gstreamer_gl_sys::gst_gl_query_local_gl_context;
+use gstreamer_gl_sys::gst_gl_texture_target_from_string;
+use gstreamer_gl_sys::gst_gl_texture_target_to_buffer_pool_option;
+use gstreamer_gl_sys::gst_gl_texture_target_to_gl;
 use gstreamer_gl_sys::gst_is_gl_memory;
+use gstreamer_gl_sys::GstGLBufferPool;
 use gstreamer_gl_sys::GstGLContext;
+use gstreamer_gl_sys::GstGLDisplay;
 use gstreamer_gl_sys::GstGLMemory;
+use gstreamer_gl_sys::GST_BUFFER_POOL_OPTION_GL_SYNC_META;
+use gstreamer_sys::GstQuery;
+use gstreamer_sys::GST_PAD_SRC;
+use gstreamer_sys::GST_PARAM_CONTROLLABLE;
+use gstreamer_video::VideoFormat;
+use gstreamer_video::VideoFrameRef;
 use gstreamer_video::VideoInfo;
+use gstreamer_video::VideoMeta;
 
-use std::cell::RefCell;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::ffi::c_void;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::fs;
+use std::mem;
+use std::os::raw::c_char;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::ptr;
 use std::rc::Rc;
+use std::slice;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::sync::Mutex;
-use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
+// External textures can't be drawn to, so aren't offered.
 const CAPS: &str = "video/x-raw(memory:GLMemory),
-  format={RGBA,RGBx},
+  format={RGBA,RGBx,RGB,RGB16,ARGB64,NV12,I420},
+  width=[1,2147483647],
+  height=[1,2147483647],
+  framerate=[0/1,2147483647/1],
+  texture-target={2D,rectangle};
+video/x-raw,
+  format={RGBA,BGRx,I420},
   width=[1,2147483647],
   height=[1,2147483647],
   framerate=[0/1,2147483647/1]";
 
+static PROPERTIES: [Property; 13] = [
+    Property("is-live", |name| {
+        ParamSpec::boolean(
+            name,
+            "Is live",
+            "Whether to act as a live source",
+            true,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("fragment-shader", |name| {
+        ParamSpec::string(
+            name,
+            "Fragment shader",
+            "Shadertoy-style GLSL source defining mainImage, using iTime, iResolution and iFrame",
+            None,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("fragment-shader-location", |name| {
+        ParamSpec::string(
+            name,
+            "Fragment shader location",
+            "File containing the fragment shader source",
+            None,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("gl-debug", |name| {
+        ParamSpec::boolean(
+            name,
+            "GL debug",
+            "Check every GL call for errors, and log KHR_debug messages",
+            false,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("pipelined", |name| {
+        ParamSpec::boolean(
+            name,
+            "Pipelined",
+            "Submit each frame before pushing the previous one",
+            false,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("scene-location", |name| {
+        ParamSpec::string(
+            name,
+            "Scene location",
+            "OBJ file to render in 3D instead of the fragment shader",
+            None,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("camera-position-x", |name| {
+        camera_param(name, "Camera position x", "Where the camera is", 0.0)
+    }),
+    Property("camera-position-y", |name| {
+        camera_param(name, "Camera position y", "Where the camera is", 0.0)
+    }),
+    Property("camera-position-z", |name| {
+        camera_param(name, "Camera position z", "Where the camera is", 5.0)
+    }),
+    Property("camera-target-x", |name| {
+        camera_param(name, "Camera target x", "Where the camera looks", 0.0)
+    }),
+    Property("camera-target-y", |name| {
+        camera_param(name, "Camera target y", "Where the camera looks", 0.0)
+    }),
+    Property("camera-target-z", |name| {
+        camera_param(name, "Camera target z", "Where the camera looks", 0.0)
+    }),
+    Property("camera-fov", |name| {
+        ParamSpec::double(
+            name,
+            "Camera field of view",
+            "Vertical field of view in degrees",
+            1.0,
+            179.0,
+            45.0,
+            controllable(),
+        )
+    }),
+];
+
+// The camera is animated with control bindings, which need controllable properties.
+fn controllable() -> ParamFlags {
+    unsafe {
+        ParamFlags::from_bits_unchecked(
+            ParamFlags::READWRITE.bits() | GST_PARAM_CONTROLLABLE as u32,
+        )
+    }
+}
+
+fn camera_param(name: &str, nick: &str, blurb: &str, default: f64) -> ParamSpec {
+    ParamSpec::double(
+        name,
+        nick,
+        blurb,
+        f64::MIN,
+        f64::MAX,
+        default,
+        controllable(),
+    )
+}
+
+#[derive(Clone)]
+struct Settings {
+    is_live: bool,
+    fragment_shader: Option<String>,
+    fragment_shader_location: Option<String>,
+    gl_debug: bool,
+    pipelined: bool,
+    scene_location: Option<String>,
+    camera: Camera,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            is_live: true,
+            fragment_shader: None,
+            fragment_shader_location: None,
+            gl_debug: false,
+            pipelined: false,
+            scene_location: None,
+            camera: Camera {
+                position: [0.0, 0.0, 5.0],
+                target: [0.0, 0.0, 0.0],
+                fov: 45.0,
+            },
+        }
+    }
+}
+
+// The element whose display and application context gst_gl_ensure_element_data is
+// filling in on this thread, and pointers to them.
+type Ensuring = (
+    *const MyGLSrc,
+    *mut *mut GstGLDisplay,
+    *mut *mut GstGLContext,
+);
+
+thread_local! {
+    static ENSURING: Cell<Option<Ensuring>> = const { Cell::new(None) };
+}
+
 pub struct MyGLSrc {
     cat: DebugCategory,
+    settings: Mutex<Settings>,
     start: Instant,
-    frame_micros: AtomicU64,
-    next_frame_micros: AtomicU64,
+    framerate: Mutex<Fraction>,
     frames: AtomicU64,
+    clock_wait: ClockWait,
     buffer_pool: Mutex<Option<BufferPool>>,
+    // The context we draw with, which is downstream's if it has one.
+    gl_context: Mutex<Option<GLContext>>,
+    // The display and application context, from the pipeline or application.
+    gl_display: Mutex<(Option<GLDisplay>, Option<GLContext>)>,
+    shader_file: Mutex<Option<(String, String)>>,
+    scene: Mutex<Option<Arc<Scene>>>,
+    gl_resources: Mutex<Option<GlResources>>,
+    producer: Mutex<Option<Box<dyn SwapChainProducer>>>,
     out_info: Mutex<Option<VideoInfo>>,
+    pending: Mutex<Option<Frame>>,
 }
 
 impl ObjectSubclass for MyGLSrc {
@@ -81,12 +344,20 @@ impl ObjectSubclass for MyGLSrc {
     fn new() -> Self {
         Self {
             cat: DebugCategory::new("myglsrc", DebugColorFlags::empty(), Some("My glsrc by me")),
+            settings: Mutex::new(Settings::default()),
             start: Instant::now(),
-            frame_micros: AtomicU64::new(16_667), // Default 60fps
-            next_frame_micros: AtomicU64::new(0),
+            framerate: Mutex::new(Fraction::new(60, 1)), // Default 60fps
             frames: AtomicU64::new(0),
+            clock_wait: ClockWait::default(),
             buffer_pool: Mutex::new(None),
+            gl_context: Mutex::new(None),
+            gl_display: Mutex::new((None, None)),
+            shader_file: Mutex::new(None),
+            scene: Mutex::new(None),
+            gl_resources: Mutex::new(None),
+            producer: Mutex::new(None),
             out_info: Mutex::new(None),
+            pending: Mutex::new(None),
         }
     }
 
@@ -102,6 +373,32 @@ impl ObjectSubclass for MyGLSrc {
         let src_pad_template =
             PadTemplate::new("src", PadDirection::Src, PadPresence::Always, &src_caps).unwrap();
         klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        // BaseSrcImpl doesn't have decide_allocation, so we override it ourselves
+        unsafe {
+            let base_src_class = &mut *(klass as *mut ClassStruct<Self> as *mut GstBaseSrcClass);
+            base_src_class.decide_allocation = Some(decide_allocation_trampoline);
+        }
+
+        // Emitted on the GL thread with the texture's framebuffer bound, so applications
+        // can draw frames themselves. Returning false drops the frame. Formats other
+        // than RGBA are drawn to an RGBA texture and converted afterwards. The texture's
+        // target (GL_TEXTURE_2D or GL_TEXTURE_RECTANGLE) follows its id.
+        klass.add_signal(
+            "draw",
+            SignalFlags::RUN_LAST | SignalFlags::ACTION,
+            &[
+                GLContext::static_type(),
+                Type::U32,
+                Type::U32,
+                Type::I32,
+                Type::I32,
+                Type::U64,
+            ],
+            Type::Bool,
+        );
     }
 
     glib_object_subclass!();
@@ -115,71 +412,144 @@ impl ObjectImpl for MyGLSrc {
         let basesrc = obj.downcast_ref::<BaseSrc>().unwrap();
         basesrc.set_live(true);
         basesrc.set_format(Format::Time);
-        basesrc.set_do_timestamp(true);
     }
-}
 
-impl ElementImpl for MyGLSrc {}
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &Value) {
+        let mut settings = self.settings.lock().unwrap();
+        match PROPERTIES[id] {
+            Property("is-live", ..) => {
+                settings.is_live = value.get().unwrap();
+                let basesrc = obj.downcast_ref::<BaseSrc>().unwrap();
+                basesrc.set_live(settings.is_live);
+            }
+            // The shader is recompiled on the next frame if its source has changed.
+            // Setting either property replaces the other.
+            Property("fragment-shader", ..) => {
+                settings.fragment_shader = value.get();
+                settings.fragment_shader_location = None;
+            }
+            Property("fragment-shader-location", ..) => {
+                settings.fragment_shader_location = value.get();
+                settings.fragment_shader = None;
+                *self.shader_file.lock().unwrap() = None;
+            }
+            Property("gl-debug", ..) => settings.gl_debug = value.get().unwrap(),
+            Property("pipelined", ..) => {
+                settings.pipelined = value.get().unwrap();
+                if !settings.pipelined {
+                    *self.pending.lock().unwrap() = None;
+                }
+            }
+            // The scene is loaded again on the next frame, even from the same location.
+            Property("scene-location", ..) => {
+                settings.scene_location = value.get();
+                *self.scene.lock().unwrap() = None;
+            }
+            Property("camera-position-x", ..) => {
+                settings.camera.position[0] = value.get::<f64>().unwrap() as f32
+            }
+            Property("camera-position-y", ..) => {
+                settings.camera.position[1] = value.get::<f64>().unwrap() as f32
+            }
+            Property("camera-position-z", ..) => {
+                settings.camera.position[2] = value.get::<f64>().unwrap() as f32
+            }
+            Property("camera-target-x", ..) => {
+                settings.camera.target[0] = value.get::<f64>().unwrap() as f32
+            }
+            Property("camera-target-y", ..) => {
+                settings.camera.target[1] = value.get::<f64>().unwrap() as f32
+            }
+            Property("camera-target-z", ..) => {
+                settings.camera.target[2] = value.get::<f64>().unwrap() as f32
+            }
+            Property("camera-fov", ..) => settings.camera.fov = value.get::<f64>().unwrap() as f32,
+            _ => unimplemented!(),
+        }
+    }
 
-thread_local! {
-    static GL: RefCell<Option<Rc<Gl>>> = RefCell::new(None);
+    fn get_property(&self, _obj: &glib::Object, id: usize) -> Result<Value, ()> {
+        let settings = self.settings.lock().unwrap();
+        match PROPERTIES[id] {
+            Property("is-live", ..) => Ok(settings.is_live.to_value()),
+            Property("fragment-shader", ..) => Ok(settings.fragment_shader.to_value()),
+            Property("fragment-shader-location", ..) => {
+                Ok(settings.fragment_shader_location.to_value())
+            }
+            Property("gl-debug", ..) => Ok(settings.gl_debug.to_value()),
+            Property("pipelined", ..) => Ok(settings.pipelined.to_value()),
+            Property("scene-location", ..) => Ok(settings.scene_location.to_value()),
+            Property("camera-position-x", ..) => {
+                Ok((settings.camera.position[0] as f64).to_value())
+            }
+            Property("camera-position-y", ..) => {
+                Ok((settings.camera.position[1] as f64).to_value())
+            }
+            Property("camera-position-z", ..) => {
+                Ok((settings.camera.position[2] as f64).to_value())
+            }
+            Property("camera-target-x", ..) => Ok((settings.camera.target[0] as f64).to_value()),
+            Property("camera-target-y", ..) => Ok((settings.camera.target[1] as f64).to_value()),
+            Property("camera-target-z", ..) => Ok((settings.camera.target[2] as f64).to_value()),
+            Property("camera-fov", ..) => Ok((settings.camera.fov as f64).to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for MyGLSrc {
+    // The pipeline or application sharing a GL display or context
+    fn set_context(&self, element: &Element, context: &Context) {
+        self.with_gl_display(|display, other_gl_context| unsafe {
+            gst_gl_handle_set_context(
+                element.as_ptr(),
+                context.as_mut_ptr(),
+                display,
+                other_gl_context,
+            )
+        });
+        self.parent_set_context(element, context);
+    }
 }
 
 impl BaseSrcImpl for MyGLSrc {
+    fn start(&self, src: &BaseSrc) -> Result<(), ErrorMessage> {
+        self.frames.store(0, Ordering::SeqCst);
+        gst_info!(self.cat, obj: src, "Started");
+        Ok(())
+    }
+
+    fn stop(&self, src: &BaseSrc) -> Result<(), ErrorMessage> {
+        *self.pending.lock().unwrap() = None;
+        self.release_gl_resources(src);
+        if let Some(pool) = self.buffer_pool.lock().unwrap().take() {
+            let _ = pool.set_active(false);
+        }
+        *self.gl_context.lock().unwrap() = None;
+        gst_info!(self.cat, obj: src, "Stopped");
+        Ok(())
+    }
+
     fn set_caps(&self, src: &BaseSrc, outcaps: &Caps) -> Result<(), LoggableError> {
         let out_info = VideoInfo::from_caps(outcaps)
             .ok_or_else(|| gst_loggable_error!(self.cat, "Failed to get video info"))?;
         gst_debug!(self.cat, obj: src, "Configured for caps {}", outcaps);
-        *self.out_info.lock().unwrap() = Some(out_info);
-
-        // Get the downstream GL context
-        let mut gst_gl_context = std::ptr::null_mut();
-        let el = src.upcast_ref::<Element>();
-        unsafe {
-            gstreamer_gl_sys::gst_gl_query_local_gl_context(
-                el.as_ptr(),
-                gstreamer_sys::GST_PAD_SRC,
-                &mut gst_gl_context,
-            );
-        }
-        if gst_gl_context.is_null() {
-            return Err(gst_loggable_error!(self.cat, "Failed to get GL context"));
-        }
-
-        // Create a new buffer pool for GL memory
-        let gst_gl_buffer_pool =
-            unsafe { gstreamer_gl_sys::gst_gl_buffer_pool_new(gst_gl_context) };
-        if gst_gl_buffer_pool.is_null() {
-            return Err(gst_loggable_error!(
-                self.cat,
-                "Failed to create buffer pool"
+        if let Some(producer) = self.producer.lock().unwrap().as_mut() {
+            producer.resize(Size2D::new(
+                out_info.width() as i32,
+                out_info.height() as i32,
             ));
         }
-        let pool = unsafe { BufferPool::from_glib_borrow(gst_gl_buffer_pool) };
-
-        // Configure the buffer pool with the negotiated caps
-        let mut config = pool.get_config();
-        let (_, size, min_buffers, max_buffers) = config.get_params().unwrap_or((None, 0, 0, 1024));
-        config.set_params(Some(outcaps), size, min_buffers, max_buffers);
-        pool.set_config(config)
-            .map_err(|_| gst_loggable_error!(self.cat, "Failed to update config"))?;
-
-        // Save the buffer pool for later use
-        *self.buffer_pool.lock().expect("Poisoned lock") = Some(pool);
+        *self.out_info.lock().unwrap() = Some(out_info.clone());
+        *self.pending.lock().unwrap() = None;
 
         // Is the framerate set?
         let framerate = outcaps
             .get_structure(0)
             .and_then(|cap| cap.get::<Fraction>("framerate"));
-        if let Some(framerate) = framerate {
-            let frame_micros = 1_000_000 * *framerate.denom() as u64 / *framerate.numer() as u64;
-            gst_debug!(
-                self.cat,
-                obj: src,
-                "Setting frame duration to {}micros",
-                frame_micros
-            );
-            self.frame_micros.store(frame_micros, Ordering::SeqCst);
+        if let Some(framerate) = framerate.filter(|framerate| *framerate.numer() > 0) {
+            gst_debug!(self.cat, obj: src, "Setting framerate to {}", framerate);
+            *self.framerate.lock().unwrap() = framerate;
         }
         Ok(())
     }
@@ -188,22 +558,96 @@ impl BaseSrcImpl for MyGLSrc {
         false
     }
 
+    fn query(&self, src: &BaseSrc, query: &mut QueryRef) -> bool {
+        if let QueryView::Context(_) = query.view() {
+            let el = src.upcast_ref::<Element>();
+            let gl_context = self.gl_context.lock().unwrap().clone();
+            let handled = self.with_gl_display(|display, other_gl_context| unsafe {
+                gst_gl_handle_context_query(
+                    el.as_ptr(),
+                    query.as_mut_ptr(),
+                    *display,
+                    gl_context.to_glib_none().0,
+                    *other_gl_context,
+                )
+            });
+            if handled != 0 {
+                return true;
+            }
+        }
+        if let QueryView::Latency(ref mut q) = query.view_mut() {
+            // A live source can't produce a frame until the end of it has passed.
+            let is_live = self.settings.lock().unwrap().is_live;
+            let latency = if is_live {
+                frame_time(*self.framerate.lock().unwrap(), 1)
+            } else {
+                ClockTime::from_nseconds(0)
+            };
+            gst_debug!(self.cat, obj: src, "Returning latency {}", latency);
+            q.set(is_live, latency, latency);
+            return true;
+        }
+        BaseSrcImplExt::parent_query(self, src, query)
+    }
+
+    fn unlock(&self, src: &BaseSrc) -> Result<(), ErrorMessage> {
+        gst_debug!(self.cat, obj: src, "Unlocking");
+        self.clock_wait.unlock();
+        Ok(())
+    }
+
+    fn unlock_stop(&self, src: &BaseSrc) -> Result<(), ErrorMessage> {
+        gst_debug!(self.cat, obj: src, "Unlock stop");
+        // A flush discards the frame in flight
+        *self.pending.lock().unwrap() = None;
+        self.clock_wait.unlock_stop();
+        Ok(())
+    }
+
     fn create(&self, src: &BaseSrc, _offset: u64, _length: u32) -> Result<Buffer, FlowError> {
-        // We block waiting for the next frame to be needed.
-        // Once get_times is in BaseSrcImpl, we can use that instead.
-        // It's been merged but not yet published.
-        // https://gitlab.freedesktop.org/gstreamer/gstreamer-rs/merge_requests/375
-        let elapsed_micros = self.start.elapsed().as_micros() as u64;
-        let frame_micros = self.frame_micros.load(Ordering::SeqCst);
-        let next_frame_micros = self
-            .next_frame_micros
-            .fetch_add(frame_micros, Ordering::SeqCst);
-        if elapsed_micros < next_frame_micros {
-            let delay = 1_000_000.min(next_frame_micros - elapsed_micros);
-            gst_debug!(self.cat, obj: src, "Waiting for {}micros", delay);
-            thread::sleep(Duration::from_micros(delay));
-            gst_debug!(self.cat, obj: src, "Done waiting");
+        // Frames dropped by the draw signal, or held back in pipelined mode, are skipped.
+        // Unlock stops us trying again, so state changes don't wait for a frame.
+        loop {
+            self.clock_wait.check_flushing()?;
+            if let Some(buffer) = self.create_frame(src)? {
+                return Ok(buffer);
+            }
+        }
+    }
+}
+
+impl MyGLSrc {
+    fn create_frame(&self, src: &BaseSrc) -> Result<Option<Buffer>, FlowError> {
+        let framerate = *self.framerate.lock().unwrap();
+        let settings = self.settings.lock().unwrap().clone();
+        let is_live = settings.is_live;
+        let shader_source = self.shader_source(src, &settings)?;
+        let scene = self.scene(src, &settings)?;
+        let swap_chain = self
+            .producer
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|producer| producer.swap_chain());
+
+        // Buffers are timestamped with their frame time, which in live mode is also
+        // the running time the clock waits for, so timestamps match the clock exactly.
+        let mut frame = self.frames.load(Ordering::SeqCst);
+        let mut discont = false;
+        if is_live {
+            let skipped = skip_missed(self.cat, src, frame, 1, |now| frame_at(framerate, now));
+            frame = skipped.0;
+            discont = skipped.1;
         }
+        self.frames.store(frame + 1, Ordering::SeqCst);
+        let pts = frame_time(framerate, frame);
+        let end = frame_time(framerate, frame + 1);
+
+        // Move the camera along its keyframed path, if it has one
+        if src.sync_values(pts).is_err() {
+            gst_debug!(self.cat, obj: src, "Failed to sync values at {}", pts);
+        }
+        let camera = self.settings.lock().unwrap().camera;
 
         // Get the buffer pool
         let pool_guard = self.buffer_pool.lock().unwrap();
@@ -215,73 +659,750 @@ impl BaseSrcImpl for MyGLSrc {
         }
 
         // Get a buffer to fill
-        let buffer = pool.acquire_buffer(None)?;
+        let mut buffer = pool.acquire_buffer(None)?;
 
-        // Get the GL memory from the buffer
-        let memory = buffer.get_all_memory().ok_or_else(|| {
-            gst_element_error!(src, CoreError::Failed, ["Failed to get memory"]);
-            FlowError::Error
-        })?;
-        let memory = unsafe { memory.into_ptr() };
-        if unsafe { gst_is_gl_memory(memory) } == 0 {
-            gst_element_error!(src, CoreError::Failed, ["Memory isn't GL memory"]);
-            return Err(FlowError::Error);
+        // The animation only depends on the frame number, so output is reproducible.
+        let frame_time = Duration::from_nanos(pts.nseconds().unwrap_or(0));
+        {
+            let buffer = buffer.get_mut().ok_or(FlowError::Error)?;
+            buffer.set_pts(pts);
+            buffer.set_duration(end - pts);
+            if discont {
+                buffer.set_flags(BufferFlags::DISCONT);
+            }
         }
-        let gl_memory = unsafe { (memory as *mut GstGLMemory).as_ref() }.ok_or_else(|| {
-            gst_element_error!(src, CoreError::Failed, ["Memory is null"]);
+
+        // Get the GL memory for each plane from the buffer
+        let planes = (0..buffer.n_memory())
+            .map(|index| {
+                let memory = unsafe { buffer.peek_memory(index).as_mut_ptr() };
+                if unsafe { gst_is_gl_memory(memory) } == 0 {
+                    gst_element_error!(src, CoreError::Failed, ["Memory isn't GL memory"]);
+                    return Err(FlowError::Error);
+                }
+                // The buffer keeps the memory alive until it's filled
+                Ok(unsafe { &*(memory as *const GstGLMemory) })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let gl_memory = *planes.first().ok_or_else(|| {
+            gst_element_error!(src, CoreError::Failed, ["Buffer has no memory"]);
             FlowError::Error
         })?;
 
         // Fill the buffer on the GL thread
-        let mut task = MyGLSrcTask {
-            my_gl_src: self,
+        let task = MyGLSrcTask {
             src,
-            gl_memory,
-            done: false,
+            planes,
+            frame,
+            pts,
+            frame_time,
+            shader_source: shader_source.as_deref(),
+            scene,
+            camera,
+            swap_chain,
+            debug: settings.gl_debug,
+        };
+        let gl_context = unsafe { GLContext::from_glib_borrow(gl_memory.mem.context) };
+        if buffer.get_meta::<GLSyncMeta>().is_none() {
+            let buffer = buffer.get_mut().ok_or(FlowError::Error)?;
+            GLSyncMeta::add(buffer, &gl_context);
+        }
+        let rendered = try_on_gl_thread(src, &gl_context, |gl_context| {
+            if !self.fill_gl_memory(gl_context, &task)? {
+                return Ok(None);
+            }
+            let pixels = self.start_readback(&task)?;
+            // Rather than waiting for the GPU, we leave a fence for whoever reads the texture
+            if let (None, Some(meta)) = (&pixels, buffer.get_meta::<GLSyncMeta>()) {
+                meta.set_sync_point(gl_context);
+            }
+            Ok(Some(pixels))
+        })?;
+        let dropped = rendered.is_none();
+        let frame = match rendered {
+            None => {
+                gst_debug!(self.cat, obj: src, "Dropping frame {}", frame);
+                None
+            }
+            Some(None) => Some(Frame {
+                buffer,
+                readback: None,
+            }),
+            // The texture goes back to the pool, since later draws wait for the read
+            // The output comes from the pool BaseSrc set up with downstream
+            Some(Some(pixels)) => {
+                let output_pool = src.get_buffer_pool().ok_or(FlowError::NotNegotiated)?;
+                if !output_pool.is_active() {
+                    output_pool.set_active(true).map_err(|_| FlowError::Error)?;
+                }
+                let mut output = output_pool.acquire_buffer(None)?;
+                {
+                    let output = output.get_mut().ok_or(FlowError::Error)?;
+                    output.set_pts(pts);
+                    output.set_duration(end - pts);
+                    if discont {
+                        output.set_flags(BufferFlags::DISCONT);
+                    }
+                }
+                Some(Frame {
+                    buffer: output,
+                    readback: Some(pixels),
+                })
+            }
+        };
+
+        // In pipelined mode this frame is pushed next time, while the one after renders
+        let frame = if settings.pipelined {
+            let mut pending = self.pending.lock().unwrap();
+            match frame {
+                Some(frame) => pending.replace(frame),
+                None => pending.take(),
+            }
+        } else {
+            frame
+        };
+        let buffer = match frame {
+            Some(frame) => Some(self.finish_frame(src, frame)?),
+            None => None,
         };
-        let data = &mut task as *mut MyGLSrcTask as *mut c_void;
-        unsafe { gst_gl_context_thread_add(gl_memory.mem.context, Some(execute_task), data) };
-        debug_assert!(task.done);
 
+        // In live mode the frame isn't pushed until the clock reaches its end.
+        // Otherwise a dropped frame still takes its duration, rather than trying
+        // the next one straight away. The waits are unscheduled by unlock, so
+        // pausing and flushing don't block.
+        if is_live {
+            let end = buffer
+                .as_ref()
+                .map(|buffer| buffer.get_pts() + buffer.get_duration())
+                .unwrap_or(end);
+            let jitter = self.clock_wait.wait_until(src, end)?;
+            gst_debug!(self.cat, obj: src, "Waited with jitter {}", jitter);
+        } else if dropped {
+            self.clock_wait.wait_for(src, end - pts)?;
+        }
         Ok(buffer)
     }
+
+    // The shader source, read from the location if it isn't set directly.
+    fn shader_source(
+        &self,
+        src: &BaseSrc,
+        settings: &Settings,
+    ) -> Result<Option<String>, FlowError> {
+        if let Some(ref source) = settings.fragment_shader {
+            return Ok(Some(source.clone()));
+        }
+        let location = match settings.fragment_shader_location {
+            Some(ref location) => location,
+            None => return Ok(None),
+        };
+        let mut shader_file = self.shader_file.lock().unwrap();
+        if let Some((ref path, ref source)) = *shader_file {
+            if path == location {
+                return Ok(Some(source.clone()));
+            }
+        }
+        gst_debug!(self.cat, obj: src, "Loading {}", location);
+        let source = fs::read_to_string(location).map_err(|err| {
+            gst_element_error!(
+                src,
+                ResourceError::Read,
+                ("Failed to read fragment shader {}", location),
+                ["{}", err]
+            );
+            FlowError::Error
+        })?;
+        *shader_file = Some((location.clone(), source.clone()));
+        Ok(Some(source))
+    }
+
+    // The scene, loaded from its location the first time it's needed.
+    fn scene(&self, src: &BaseSrc, settings: &Settings) -> Result<Option<Arc<Scene>>, FlowError> {
+        let location = match settings.scene_location {
+            Some(ref location) => location,
+            None => return Ok(None),
+        };
+        let mut scene = self.scene.lock().unwrap();
+        if let Some(ref scene) = *scene {
+            if scene.location == *location {
+                return Ok(Some(scene.clone()));
+            }
+        }
+        gst_debug!(self.cat, obj: src, "Loading {}", location);
+        let source = fs::read_to_string(location).map_err(|err| {
+            gst_element_error!(
+                src,
+                ResourceError::Read,
+                ("Failed to read scene {}", location),
+                ["{}", err]
+            );
+            FlowError::Error
+        })?;
+        let mesh = Mesh::from_obj(&source).map_err(|err| {
+            gst_element_error!(
+                src,
+                StreamError::Decode,
+                ("Failed to parse scene {}", location),
+                ["{}", err]
+            );
+            FlowError::Error
+        })?;
+        let new_scene = Arc::new(Scene {
+            location: location.clone(),
+            mesh,
+        });
+        *scene = Some(new_scene.clone());
+        Ok(Some(new_scene))
+    }
+
+    // Releases the GL resources on the GL thread of the context they belong to.
+    fn release_gl_resources(&self, src: &BaseSrc) {
+        let resources = match self.gl_resources.lock().unwrap().take() {
+            Some(resources) => resources,
+            None => return,
+        };
+        gst_debug!(self.cat, obj: src, "Releasing GL resources");
+        let gl_context = resources.gl_context.clone();
+        run_on_gl_thread(&gl_context, move |gl_context| {
+            if gl_context.activate(true).is_ok() {
+                resources.release();
+            } else {
+                resources.abandon();
+            }
+        });
+    }
+
+    // Runs a gst_gl_* helper that may replace the display or application context,
+    // through pointers to owned references like a C element's fields.
+    fn with_gl_display<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(*mut *mut GstGLDisplay, *mut *mut GstGLContext) -> R,
+    {
+        // Called back from gst_gl_ensure_element_data, which holds the lock
+        let ensuring = ENSURING.with(Cell::get);
+        if let Some((src, display, other_gl_context)) = ensuring {
+            if ptr::eq(src, self) {
+                return f(display, other_gl_context);
+            }
+        }
+        let mut shared = self.gl_display.lock().unwrap();
+        let mut display: *mut GstGLDisplay = shared.0.to_glib_full();
+        let mut other_gl_context: *mut GstGLContext = shared.1.to_glib_full();
+        let result = f(&mut display, &mut other_gl_context);
+        *shared = unsafe { (from_glib_full(display), from_glib_full(other_gl_context)) };
+        result
+    }
+
+    // Finds the GL context to draw with. The display and application context come
+    // from the pipeline or application, or failing that a new display is made and shared.
+    fn ensure_gl_context(&self, src: &BaseSrc) -> Result<GLContext, LoggableError> {
+        let el = src.upcast_ref::<Element>();
+        let found = self.with_gl_display(|display, other_gl_context| {
+            // It sets the context on us, which has to write to the same references
+            let previous = ENSURING.with(|ensuring| {
+                ensuring.replace(Some((self as *const Self, display, other_gl_context)))
+            });
+            let found = unsafe {
+                gst_gl_ensure_element_data(el.as_ptr() as *mut c_void, display, other_gl_context)
+            };
+            ENSURING.with(|ensuring| ensuring.set(previous));
+            found
+        });
+        let (display, other_gl_context) = self.gl_display.lock().unwrap().clone();
+        let display = match display {
+            Some(display) if found != 0 => display,
+            _ => return Err(gst_loggable_error!(self.cat, "Failed to get a GL display")),
+        };
+
+        // Downstream's context, so its elements can use our textures directly
+        let mut gst_gl_context = ptr::null_mut();
+        unsafe {
+            gst_gl_query_local_gl_context(el.as_ptr(), GST_PAD_SRC, &mut gst_gl_context);
+        }
+        let downstream: Option<GLContext> = unsafe { from_glib_full(gst_gl_context) };
+        let mut current = self.gl_context.lock().unwrap();
+        if let Some(gl_context) = downstream {
+            *current = Some(gl_context.clone());
+            return Ok(gl_context);
+        }
+
+        // Otherwise we keep using our own, as long as the display hasn't changed.
+        // GStreamer picks the platform, so on a GPU-less machine this is EGL
+        // with a software renderer, configured with GST_GL_PLATFORM/GST_GL_WINDOW.
+        if let Some(gl_context) = current.as_ref() {
+            if gl_context.get_display() == display {
+                return Ok(gl_context.clone());
+            }
+        }
+        gst_info!(self.cat, obj: src, "No downstream GL context, making one");
+        let gl_context = match create_gl_context(&display, other_gl_context.as_ref()) {
+            Ok(gl_context) => gl_context,
+            Err(err) => self.create_headless_gl_context(src, &display, err)?,
+        };
+        *current = Some(gl_context.clone());
+        Ok(gl_context)
+    }
+
+    // The default display can be a window system without GL, such as an X server
+    // with no GLX, so try a headless EGL display before giving up. GStreamer can't
+    // use contexts from other GL libraries like OSMesa, since it needs to own the
+    // context's thread and load its functions.
+    #[cfg(target_os = "linux")]
+    fn create_headless_gl_context(
+        &self,
+        src: &BaseSrc,
+        display: &GLDisplay,
+        err: glib::Error,
+    ) -> Result<GLContext, LoggableError> {
+        if display.is::<GLDisplayEGL>() {
+            return Err(gst_loggable_error!(
+                self.cat,
+                "Failed to create GL context: {}",
+                err
+            ));
+        }
+        gst_info!(self.cat, obj: src, "Failed to create GL context ({}), trying EGL", err);
+        let egl_display = GLDisplayEGL::new().upcast::<GLDisplay>();
+        let gl_context = create_gl_context(&egl_display, None)
+            .map_err(|err| gst_loggable_error!(self.cat, "Failed to create GL context: {}", err))?;
+        // Keep the display, so we don't make another context next time
+        self.gl_display.lock().unwrap().0 = Some(egl_display);
+        Ok(gl_context)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn create_headless_gl_context(
+        &self,
+        _src: &BaseSrc,
+        _display: &GLDisplay,
+        err: glib::Error,
+    ) -> Result<GLContext, LoggableError> {
+        Err(gst_loggable_error!(
+            self.cat,
+            "Failed to create GL context: {}",
+            err
+        ))
+    }
+
+    // Sets up the GL resources and buffer pool for the negotiated caps, preferring
+    // a GL pool offered by downstream. Called again when renegotiating.
+    fn decide_allocation(&self, src: &BaseSrc, query: &mut QueryRef) -> Result<(), LoggableError> {
+        let (outcaps, proposed) = match query.view() {
+            QueryView::Allocation(ref q) => {
+                (q.get_owned().0, q.get_allocation_pools().into_iter().next())
+            }
+            _ => return Err(gst_loggable_error!(self.cat, "Not an allocation query")),
+        };
+        let out_info = VideoInfo::from_caps(&outcaps)
+            .ok_or_else(|| gst_loggable_error!(self.cat, "Failed to get video info"))?;
+
+        // For system memory we render RGBA textures, and read them back
+        let system_memory = !outcaps
+            .get_features(0)
+            .is_some_and(|features| features.contains("memory:GLMemory"));
+        let gl_caps = if system_memory {
+            let mut gl_caps =
+                VideoInfo::new(VideoFormat::Rgba, out_info.width(), out_info.height())
+                    .fps(out_info.fps())
+                    .build()
+                    .and_then(|info| info.to_caps())
+                    .ok_or_else(|| gst_loggable_error!(self.cat, "Failed to make GL caps"))?;
+            gl_caps
+                .get_mut()
+                .unwrap()
+                .set_features(0, Some(CapsFeatures::new(&["memory:GLMemory"])));
+            gl_caps
+        } else {
+            outcaps.clone()
+        };
+
+        // The new pool has new textures, and maybe a new context, so start afresh
+        let gl_context = self.ensure_gl_context(src)?;
+        self.release_gl_resources(src);
+        let debug = self.settings.lock().unwrap().gl_debug;
+        let mut result = Ok(());
+        run_on_gl_thread(&gl_context, |gl_context| {
+            gst_debug!(self.cat, obj: src, "Creating GL resources");
+            let mut resources = GlResources::new(gl_context);
+            if system_memory {
+                match Readback::new(&resources.gl, gl_context, &out_info, debug) {
+                    Ok(readback) => resources.readback = Some(readback),
+                    Err(err) => result = Err(err),
+                }
+            } else {
+                match Converter::new(&resources.gl, gl_context, &out_info, debug) {
+                    Ok(converter) => resources.converter = converter,
+                    Err(err) => result = Err(err),
+                }
+            }
+            *self.gl_resources.lock().unwrap() = Some(resources);
+        });
+        result.map_err(|err| gst_loggable_error!(self.cat, "Failed to set up formats: {}", err))?;
+
+        // The old pool can't be reconfigured while it's active
+        if let Some(pool) = self.buffer_pool.lock().unwrap().take() {
+            let _ = pool.set_active(false);
+        }
+
+        // Use downstream's GL pool if it's for our context, otherwise make one.
+        // In system memory, downstream's pools are for the output, not our textures.
+        let gl_pool_type = unsafe { from_glib(gst_gl_buffer_pool_get_type()) };
+        let (pool, size, min_buffers, max_buffers, offered) = match proposed {
+            Some((Some(pool), size, min_buffers, max_buffers))
+                if !system_memory
+                    && pool.get_type().is_a(&gl_pool_type)
+                    && unsafe { (*(pool.as_ptr() as *mut GstGLBufferPool)).context }
+                        == gl_context.as_ptr() =>
+            {
+                gst_debug!(self.cat, obj: src, "Using downstream's GL pool");
+                (pool, size, min_buffers, max_buffers, true)
+            }
+            proposed => {
+                let pool: Option<BufferPool> =
+                    unsafe { from_glib_full(gst_gl_buffer_pool_new(gl_context.as_ptr())) };
+                let pool = pool
+                    .ok_or_else(|| gst_loggable_error!(self.cat, "Failed to create buffer pool"))?;
+                // Downstream's sizes still apply if its pool wasn't a GL one
+                match proposed {
+                    Some((_, size, min_buffers, max_buffers)) if !system_memory => {
+                        (pool, size, min_buffers, max_buffers, true)
+                    }
+                    _ => {
+                        let (_, size, min_buffers, max_buffers) =
+                            pool.get_config().get_params().unwrap_or((None, 0, 0, 1024));
+                        (pool, size, min_buffers, max_buffers, false)
+                    }
+                }
+            }
+        };
+
+        // Configure the buffer pool with the negotiated caps
+        let mut config = pool.get_config();
+        config.set_params(Some(&gl_caps), size, min_buffers, max_buffers);
+        // So downstream can wait for rendering to finish on its own context
+        let sync_meta = unsafe { CStr::from_ptr(GST_BUFFER_POOL_OPTION_GL_SYNC_META) };
+        config.add_option(&sync_meta.to_string_lossy());
+        let texture_target = gl_caps
+            .get_structure(0)
+            .and_then(|structure| structure.get::<&str>("texture-target"))
+            .and_then(|target| CString::new(target).ok());
+        if let Some(texture_target) = texture_target {
+            let option = unsafe {
+                let target = gst_gl_texture_target_from_string(texture_target.as_ptr());
+                CStr::from_ptr(gst_gl_texture_target_to_buffer_pool_option(target))
+            };
+            config.add_option(&option.to_string_lossy());
+        }
+        pool.set_config(config)
+            .map_err(|_| gst_loggable_error!(self.cat, "Failed to update config"))?;
+
+        // Tell downstream which pool we picked, unless it's only for our textures
+        if !system_memory {
+            if let QueryView::Allocation(ref mut q) = query.view_mut() {
+                if offered {
+                    q.set_nth_allocation_pool(0, Some(&pool), size, min_buffers, max_buffers);
+                } else {
+                    q.add_allocation_pool(Some(&pool), size, min_buffers, max_buffers);
+                }
+            }
+        }
+
+        // Save the buffer pool for later use
+        *self.buffer_pool.lock().expect("Poisoned lock") = Some(pool);
+
+        Ok(())
+    }
 }
 
 struct MyGLSrcTask<'a> {
-    my_gl_src: &'a MyGLSrc,
     src: &'a BaseSrc,
-    gl_memory: &'a GstGLMemory,
-    done: bool,
+    planes: Vec<&'a GstGLMemory>,
+    frame: u64,
+    pts: ClockTime,
+    frame_time: Duration,
+    shader_source: Option<&'a str>,
+    scene: Option<Arc<Scene>>,
+    camera: Camera,
+    swap_chain: Option<SwapChain>,
+    debug: bool,
+}
+
+// A rendered frame. In system memory it still has to be read back into the buffer.
+struct Frame {
+    buffer: Buffer,
+    readback: Option<Pixels>,
+}
+
+// For applications that link to this crate and register the plugin with
+// `plugin_register_static`: frames are taken from the producer's swap chain
+// rather than drawn by the element. None goes back to drawing them.
+pub fn set_producer(
+    element: &Element,
+    mut producer: Option<Box<dyn SwapChainProducer>>,
+) -> Result<(), glib::BoolError> {
+    if !element.get_type().is_a(&MyGLSrc::get_type()) {
+        return Err(glib_bool_error!("Not a myglsrc element"));
+    }
+    let src = element.downcast_ref::<BaseSrc>().unwrap();
+    let my_gl_src = MyGLSrc::from_instance(src);
+    gst_info!(my_gl_src.cat, obj: src, "Setting producer");
+    if let Some(out_info) = my_gl_src.out_info.lock().unwrap().as_ref() {
+        if let Some(producer) = producer.as_mut() {
+            producer.resize(Size2D::new(
+                out_info.width() as i32,
+                out_info.height() as i32,
+            ));
+        }
+    }
+    *my_gl_src.producer.lock().unwrap() = producer;
+    Ok(())
+}
+
+// An application rendering frames for myglsrc with surfman. Its swap chain's
+// surfaces must be compatible with the GL context's surfman device, which on X11
+// means using the same display.
+pub trait SwapChainProducer: Send {
+    // The swap chain whose front buffer becomes the next frame.
+    fn swap_chain(&self) -> SwapChain;
+
+    // Called when the frame size is negotiated. Front buffers of other sizes are scaled.
+    fn resize(&mut self, size: Size2D<i32>);
+}
+
+// A swap chain on its own is a producer that renders at whatever size it likes.
+impl SwapChainProducer for SwapChain {
+    fn swap_chain(&self) -> SwapChain {
+        self.clone()
+    }
+
+    fn resize(&mut self, _size: Size2D<i32>) {}
+}
+
+// GL state for one context. It's only used on that context's GL thread.
+struct GlResources {
+    gl_context: GLContext,
+    gl: Rc<Gl>,
+    // The pool's textures are reused, so each gets a framebuffer once.
+    fbos: HashMap<gl::GLuint, gl::GLuint>,
+    shader: Option<FragmentShader>,
+    scene: Option<SceneRenderer>,
+    // The GL context wrapped for surfman, and the front buffer we last drew.
+    surfman: Option<(Device, SurfmanContext)>,
+    front_buffer: Option<(SwapChain, Surface)>,
+    read_fbo: Option<gl::GLuint>,
+    readback: Option<Readback>,
+    converter: Option<Converter>,
+}
+
+// The GL bindings aren't Send, but we only use them on the GL thread.
+unsafe impl Send for GlResources {}
+
+impl GlResources {
+    // Runs on the GL thread
+    fn new(gl_context: &GLContext) -> GlResources {
+        let gl = Gl::gl_fns(gl::ffi_gl::Gl::load_with(|s| {
+            gl_context.get_proc_address(s) as *const _
+        }));
+        GlResources {
+            gl_context: gl_context.clone(),
+            gl,
+            fbos: HashMap::new(),
+            shader: None,
+            scene: None,
+            surfman: None,
+            front_buffer: None,
+            read_fbo: None,
+            readback: None,
+            converter: None,
+        }
+    }
+
+    // Runs on the GL thread, leaving the plane's framebuffer bound.
+    fn bind_fbo(&mut self, plane: &GstGLMemory, check: &GlCheck) {
+        let gl = &self.gl;
+        let texture_id = plane.tex_id;
+        let target = unsafe { gst_gl_texture_target_to_gl(plane.tex_target) };
+        let fbo = *self.fbos.entry(texture_id).or_insert_with(|| {
+            let fbo = gl.gen_framebuffers(1)[0];
+            check.after("glGenFramebuffers");
+            gl.bind_framebuffer(gl::FRAMEBUFFER, fbo);
+            check.after("glBindFramebuffer");
+            gl.framebuffer_texture_2d(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                target,
+                texture_id,
+                0,
+            );
+            check.after("glFramebufferTexture2D");
+            fbo
+        });
+        gl.bind_framebuffer(gl::FRAMEBUFFER, fbo);
+        check.after("glBindFramebuffer");
+    }
+
+    // Runs on the GL thread
+    fn release(self) {
+        let mut fbos: Vec<gl::GLuint> = self.fbos.values().cloned().collect();
+        fbos.extend(self.read_fbo);
+        self.gl.delete_framebuffers(&fbos);
+        if let Some(shader) = self.shader {
+            shader.delete(&self.gl);
+        }
+        if let Some(scene) = self.scene {
+            scene.delete(&self.gl);
+        }
+        if let Some(readback) = self.readback {
+            readback.delete(&self.gl);
+        }
+        if let Some(converter) = self.converter {
+            converter.delete(&self.gl);
+        }
+        if let Some((swap_chain, surface)) = self.front_buffer {
+            swap_chain.recycle_surface(surface);
+        }
+        if let Some((device, mut context)) = self.surfman {
+            let _ = device.destroy_context(&mut context);
+        }
+    }
+
+    // Gives up on resources we can't release, because their context isn't current.
+    fn abandon(self) {
+        if let Some((swap_chain, surface)) = self.front_buffer {
+            swap_chain.recycle_surface(surface);
+        }
+        // surfman insists contexts are destroyed, but this one is just a wrapper
+        mem::forget(self.surfman);
+    }
 }
 
-unsafe extern "C" fn execute_task(context: *mut GstGLContext, data: *mut c_void) {
-    let task = &mut *(data as *mut MyGLSrcTask);
-    let gl_context = GLContext::from_glib_borrow(context);
-    task.my_gl_src
-        .fill_gl_memory(task.src, gl_context, task.gl_memory);
-    task.done = true;
+// Without a handler, the draw signal would return false and drop every frame.
+fn has_draw_handler(src: &BaseSrc) -> bool {
+    unsafe {
+        let signal_id = g_signal_lookup(
+            b"draw\0".as_ptr() as *const c_char,
+            MyGLSrc::get_type().to_glib(),
+        );
+        g_signal_has_handler_pending(src.as_ptr() as *mut GObject, signal_id, 0, 0) != 0
+    }
+}
+
+unsafe extern "C" fn decide_allocation_trampoline(
+    ptr: *mut GstBaseSrc,
+    query: *mut GstQuery,
+) -> gboolean {
+    let instance = &*(ptr as *mut ElementInstanceStruct<MyGLSrc>);
+    let imp = instance.get_impl();
+    let src: BaseSrc = from_glib_borrow(ptr);
+    let query = QueryRef::from_mut_ptr(query);
+    if let Err(err) = imp.decide_allocation(&src, query) {
+        err.log_with_object(&src);
+        return GFALSE;
+    }
+    // Let BaseSrc set up downstream's pool for system memory
+    let parent_class = MyGLSrc::type_data().as_ref().get_parent_class() as *mut GstBaseSrcClass;
+    match (*parent_class).decide_allocation {
+        Some(f) => f(ptr, query.as_mut_ptr()),
+        None => GTRUE,
+    }
+}
+
+fn create_gl_context(
+    display: &GLDisplay,
+    other_gl_context: Option<&GLContext>,
+) -> Result<GLContext, glib::Error> {
+    let gl_context = GLContext::new(display);
+    gl_context.create(other_gl_context)?;
+    Ok(gl_context)
+}
+
+// Runs a closure on the GL thread of a context, and waits for it to finish.
+fn run_on_gl_thread<F: FnOnce(&GLContext)>(gl_context: &GLContext, f: F) {
+    unsafe extern "C" fn trampoline<F: FnOnce(&GLContext)>(
+        context: *mut GstGLContext,
+        data: *mut c_void,
+    ) {
+        let f = &mut *(data as *mut Option<F>);
+        let gl_context = GLContext::from_glib_borrow(context);
+        if let Some(f) = f.take() {
+            f(&gl_context);
+        }
+    }
+    let mut f = Some(f);
+    let data = &mut f as *mut Option<F> as *mut c_void;
+    unsafe { gst_gl_context_thread_add(gl_context.as_ptr(), Some(trampoline::<F>), data) };
+}
+
+// Runs a fallible closure on the GL thread. A panic can't unwind through the
+// C callback, so we turn it into an error.
+fn try_on_gl_thread<T, F: FnOnce(&GLContext) -> Result<T, FlowError>>(
+    src: &BaseSrc,
+    gl_context: &GLContext,
+    f: F,
+) -> Result<T, FlowError> {
+    let mut result = Err(FlowError::Error);
+    run_on_gl_thread(gl_context, |gl_context| {
+        result = panic::catch_unwind(AssertUnwindSafe(|| f(gl_context))).unwrap_or_else(|_| {
+            gst_element_error!(src, LibraryError::Failed, ["Panicked on the GL thread"]);
+            Err(FlowError::Error)
+        });
+    });
+    result
 }
 
 impl MyGLSrc {
     // Runs on the GL thread
-    fn fill_gl_memory(&self, src: &BaseSrc, gl_context: GLContext, gl_memory: &GstGLMemory) {
+    // Returns whether the frame should be emitted.
+    fn fill_gl_memory(
+        &self,
+        gl_context: &GLContext,
+        task: &MyGLSrcTask,
+    ) -> Result<bool, FlowError> {
+        let src = task.src;
+
         // Get the data out of the memory
-        let draw_texture_id = gl_memory.tex_id;
-        let height = gl_memory.info.height;
-        let width = gl_memory.info.width;
-
-        // Get the GL bindings
-        let gl = GL.with(|gl| {
-            gl.borrow_mut()
-                .get_or_insert_with(|| {
-                    Gl::gl_fns(gl::ffi_gl::Gl::load_with(|s| {
-                        gl_context.get_proc_address(s) as *const _
-                    }))
-                })
-                .clone()
-        });
-        gl_context.activate(true).unwrap();
-        assert_eq!(gl.get_error(), gl::NO_ERROR);
+        let height = task.planes[0].info.height;
+        let width = task.planes[0].info.width;
+
+        // Get the GL resources for this context
+        let mut gl_resources = self.gl_resources.lock().unwrap();
+        let current = match *gl_resources {
+            Some(ref resources) => resources.gl_context == *gl_context,
+            None => false,
+        };
+        if !current {
+            // They're made when the caps are set, so this shouldn't happen.
+            // Any we have belong to another context, so can't be released here.
+            gst_warning!(self.cat, obj: src, "Creating GL resources for a new context");
+            if let Some(resources) = gl_resources.replace(GlResources::new(gl_context)) {
+                resources.abandon();
+            }
+        }
+        let resources = gl_resources.as_mut().unwrap();
+        let gl = resources.gl.clone();
+        self.activate(src, gl_context)?;
+
+        // Errors left behind by other users of the context aren't ours to report
+        if let Err(err) = check_error(&gl, "An earlier GL call") {
+            gst_warning!(self.cat, obj: src, "Ignoring {}", err);
+        }
+        if task.debug {
+            self.enable_debug_output(src, gl_context, &gl);
+        }
+
+        // Formats we can't draw directly are drawn in RGBA and converted
+        let check = GlCheck::new(&gl, task.debug);
+        let (draw_texture_id, draw_target) = match resources.converter {
+            Some(ref converter) => {
+                converter.bind(&gl, &check);
+                (converter.texture(), gl::TEXTURE_2D)
+            }
+            None => {
+                resources.bind_fbo(task.planes[0], &check);
+                let target = unsafe { gst_gl_texture_target_to_gl(task.planes[0].tex_target) };
+                (task.planes[0].tex_id, target)
+            }
+        };
 
         gst_debug!(
             self.cat,
@@ -292,39 +1413,512 @@ impl MyGLSrc {
             draw_texture_id,
         );
 
-        let millis = self.start.elapsed().subsec_millis();
-        let brightness = if millis < 500 {
-            (millis as f32) / 500.0
-        } else {
-            (1000.0 - millis as f32) / 500.0
+        let result = check
+            .finish("Binding the framebuffer")
+            .map_err(|err| self.gl_error(src, err))
+            .and_then(|()| {
+                if has_draw_handler(src) {
+                    self.emit_draw(
+                        gl_context,
+                        task,
+                        draw_texture_id,
+                        draw_target,
+                        width,
+                        height,
+                    )
+                } else {
+                    self.draw_frame(gl_context, resources, task, width, height)
+                        .map(|()| true)
+                }
+            })
+            .and_then(|emit| {
+                if emit {
+                    self.convert_planes(resources, task)?;
+                }
+                Ok(emit)
+            });
+
+        // Unbind even if drawing failed
+        gl.bind_framebuffer(gl::FRAMEBUFFER, 0);
+        let emit = result?;
+
+        let frame = task.frame;
+        if frame % 100 == 0 {
+            let elapsed_micros = self.start.elapsed().as_micros().max(1) as u64;
+            let fps = (frame * 1_000_000) / elapsed_micros;
+            gst_info!(self.cat, obj: src, "fps = {}", fps);
+        }
+        Ok(emit)
+    }
+
+    // Runs on the GL thread, after the frame is drawn in RGBA.
+    fn convert_planes(
+        &self,
+        resources: &mut GlResources,
+        task: &MyGLSrcTask,
+    ) -> Result<(), FlowError> {
+        let converter = match resources.converter.take() {
+            Some(converter) => converter,
+            None => return Ok(()),
+        };
+        let gl = resources.gl.clone();
+        let mut result = Ok(());
+        for (index, plane) in task.planes.iter().enumerate() {
+            let check = GlCheck::new(&gl, task.debug);
+            resources.bind_fbo(plane, &check);
+            let (width, height) = unsafe {
+                let plane = *plane as *const GstGLMemory as *mut GstGLMemory;
+                (
+                    gst_gl_memory_get_texture_width(plane),
+                    gst_gl_memory_get_texture_height(plane),
+                )
+            };
+            result = check
+                .finish("Binding the plane's framebuffer")
+                .and_then(|()| converter.draw_plane(&gl, task.debug, index, width, height));
+            if result.is_err() {
+                break;
+            }
+        }
+        resources.converter = Some(converter);
+        result.map_err(|err| self.gl_error(task.src, err))
+    }
+
+    // Runs on the GL thread
+    fn activate(&self, src: &BaseSrc, gl_context: &GLContext) -> Result<(), FlowError> {
+        gl_context.activate(true).map_err(|err| {
+            gst_element_error!(
+                src,
+                LibraryError::Failed,
+                ("Failed to activate GL context"),
+                ["{}", err]
+            );
+            FlowError::Error
+        })
+    }
+
+    // Runs on the GL thread, after the frame is drawn. Returns None if
+    // the texture is the output, otherwise the pixels being read back.
+    fn start_readback(&self, task: &MyGLSrcTask) -> Result<Option<Pixels>, FlowError> {
+        let src = task.src;
+        let mut gl_resources = self.gl_resources.lock().unwrap();
+        let resources = match gl_resources.as_mut() {
+            Some(resources) if resources.readback.is_some() => resources,
+            _ => return Ok(None),
+        };
+        let gl = resources.gl.clone();
+        let texture_id = task.planes[0].tex_id;
+        let check = GlCheck::new(&gl, task.debug);
+        resources.bind_fbo(task.planes[0], &check);
+        let result = check.finish("Binding the framebuffer").and_then(|()| {
+            resources
+                .readback
+                .as_mut()
+                .unwrap()
+                .start(&gl, task.debug, texture_id)
+        });
+        gl.bind_framebuffer(gl::FRAMEBUFFER, 0);
+        result.map(Some).map_err(|err| self.gl_error(src, err))
+    }
+
+    // Waits for any readback to finish, returning the buffer to push.
+    fn finish_frame(&self, src: &BaseSrc, frame: Frame) -> Result<Buffer, FlowError> {
+        let Frame {
+            mut buffer,
+            readback,
+        } = frame;
+        let pixels = match readback {
+            Some(pixels) => pixels,
+            None => return Ok(buffer),
+        };
+        let out_info = self
+            .out_info
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(FlowError::NotNegotiated)?;
+
+        // Downstream's pool may lay the frame out with its own strides, in which
+        // case we read into a packed frame and copy it across.
+        let packed = buffer.get_meta::<VideoMeta>().is_none_or(|meta| {
+            meta.get_stride() == out_info.stride() && meta.get_offset() == out_info.offset()
+        });
+        if packed {
+            self.read_pixels(src, pixels, &mut buffer)?;
+            return Ok(buffer);
+        }
+        let mut packed = Buffer::with_size(out_info.size()).ok_or(FlowError::Error)?;
+        self.read_pixels(src, pixels, &mut packed)?;
+        {
+            let from = VideoFrameRef::from_buffer_ref_readable(packed.as_ref(), &out_info);
+            let to = buffer
+                .get_mut()
+                .and_then(|buffer| VideoFrameRef::from_buffer_ref_writable(buffer, &out_info));
+            let copied = match (from, to) {
+                (Some(from), Some(mut to)) => from.copy(&mut to).is_ok(),
+                _ => false,
+            };
+            if !copied {
+                gst_element_error!(src, CoreError::Failed, ["Failed to copy the frame"]);
+                return Err(FlowError::Error);
+            }
+        }
+        Ok(buffer)
+    }
+
+    // Copies pixels read back from the GPU into a buffer, which is packed.
+    fn read_pixels(
+        &self,
+        src: &BaseSrc,
+        pixels: Pixels,
+        buffer: &mut Buffer,
+    ) -> Result<(), FlowError> {
+        let gl_context = self
+            .gl_resources
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|resources| resources.gl_context.clone())
+            .ok_or(FlowError::Error)?;
+        let debug = self.settings.lock().unwrap().gl_debug;
+        let mut map = buffer
+            .get_mut()
+            .and_then(|buffer| buffer.map_writable())
+            .ok_or_else(|| {
+                gst_element_error!(src, CoreError::Failed, ["Failed to map buffer"]);
+                FlowError::Error
+            })?;
+        let data = map.as_mut_slice();
+        try_on_gl_thread(src, &gl_context, |gl_context| {
+            self.activate(src, gl_context)?;
+            let gl_resources = self.gl_resources.lock().unwrap();
+            let resources = gl_resources.as_ref().ok_or(FlowError::Error)?;
+            let readback = resources.readback.as_ref().ok_or(FlowError::Error)?;
+            readback
+                .finish(&resources.gl, debug, pixels, data)
+                .map_err(|err| self.gl_error(src, err))
+        })
+    }
+
+    // Runs on the GL thread, with the framebuffer bound
+    fn emit_draw(
+        &self,
+        gl_context: &GLContext,
+        task: &MyGLSrcTask,
+        texture_id: gl::GLuint,
+        target: gl::GLenum,
+        width: i32,
+        height: i32,
+    ) -> Result<bool, FlowError> {
+        let src = task.src;
+        let pts = task.pts.to_glib();
+        let emit = src
+            .emit(
+                "draw",
+                &[gl_context, &texture_id, &target, &width, &height, &pts],
+            )
+            .map_err(|err| {
+                gst_element_error!(src, LibraryError::Failed, ["{}", err]);
+                FlowError::Error
+            })?
+            .and_then(|value| value.get::<bool>())
+            .unwrap_or(false);
+
+        // The handler may have made another context current
+        self.activate(src, gl_context)?;
+        Ok(emit)
+    }
+
+    // Runs on the GL thread, with the framebuffer bound
+    fn draw_frame(
+        &self,
+        gl_context: &GLContext,
+        resources: &mut GlResources,
+        task: &MyGLSrcTask,
+        width: i32,
+        height: i32,
+    ) -> Result<(), FlowError> {
+        if let Some(ref swap_chain) = task.swap_chain {
+            return self.draw_swap_chain(gl_context, resources, task, swap_chain, width, height);
+        }
+
+        let src = task.src;
+        let gl = &*resources.gl;
+
+        // Upload the scene if it's new or has changed
+        let renderer = &mut resources.scene;
+        let changed = match (renderer.as_ref(), task.scene.as_ref()) {
+            (Some(renderer), Some(scene)) => !Arc::ptr_eq(renderer.scene(), scene),
+            (None, None) => false,
+            _ => true,
+        };
+        if changed {
+            if let Some(old_renderer) = renderer.take() {
+                old_renderer.delete(gl);
+            }
+            if let Some(ref scene) = task.scene {
+                gst_debug!(self.cat, obj: src, "Uploading scene");
+                let api = gl_context.get_gl_api();
+                let new_renderer =
+                    SceneRenderer::new(gl, api, scene.clone(), task.debug).map_err(|err| {
+                        gst_element_error!(
+                            src,
+                            LibraryError::Failed,
+                            ("Failed to build scene"),
+                            ["{}", err]
+                        );
+                        FlowError::Error
+                    })?;
+                *renderer = Some(new_renderer);
+            }
+        }
+        if let Some(renderer) = renderer.as_mut() {
+            return renderer
+                .draw(gl, task.debug, &task.camera, width, height)
+                .map_err(|err| self.gl_error(src, err));
+        }
+
+        // Compile the shader if it's new or has changed
+        let shader = &mut resources.shader;
+        let changed = match (shader.as_ref(), task.shader_source) {
+            (Some(shader), Some(source)) => shader.source() != source,
+            (None, None) => false,
+            _ => true,
         };
+        if changed {
+            if let Some(old_shader) = shader.take() {
+                old_shader.delete(gl);
+            }
+            if let Some(source) = task.shader_source {
+                gst_debug!(self.cat, obj: src, "Compiling fragment shader");
+                let api = gl_context.get_gl_api();
+                let new_shader =
+                    FragmentShader::new(gl, api, source, task.debug).map_err(|err| {
+                        gst_element_error!(
+                            src,
+                            LibraryError::Failed,
+                            ("Failed to build fragment shader"),
+                            ["{}", err]
+                        );
+                        FlowError::Error
+                    })?;
+                *shader = Some(new_shader);
+            }
+        }
+
+        match shader.as_ref() {
+            Some(shader) => {
+                let time = task.frame_time.as_secs_f32();
+                shader
+                    .draw(gl, task.debug, width, height, time, task.frame as i32)
+                    .map_err(|err| self.gl_error(src, err))
+            }
+            None => {
+                let millis = task.frame_time.subsec_millis();
+                let brightness = if millis < 500 {
+                    (millis as f32) / 500.0
+                } else {
+                    (1000.0 - millis as f32) / 500.0
+                };
+                let check = GlCheck::new(gl, task.debug);
+                gl.clear_color(brightness, brightness, brightness, 1.0);
+                check.after("glClearColor");
+                gl.clear(gl::COLOR_BUFFER_BIT);
+                check
+                    .finish("glClear")
+                    .map_err(|err| self.gl_error(src, err))
+            }
+        }
+    }
+
+    // Runs on the GL thread, with the framebuffer bound. Draws the producer's
+    // latest front buffer, or the last one we drew if it hasn't swapped since.
+    fn draw_swap_chain(
+        &self,
+        gl_context: &GLContext,
+        resources: &mut GlResources,
+        task: &MyGLSrcTask,
+        swap_chain: &SwapChain,
+        width: i32,
+        height: i32,
+    ) -> Result<(), FlowError> {
+        let src = task.src;
+        let gl = &*resources.gl;
+
+        if let Some(surface) = swap_chain.take_surface() {
+            let front_buffer = (swap_chain.clone(), surface);
+            if let Some((swap_chain, surface)) = resources.front_buffer.replace(front_buffer) {
+                swap_chain.recycle_surface(surface);
+            }
+        }
+        let (front_swap_chain, surface) = match resources.front_buffer.take() {
+            Some(front_buffer) => front_buffer,
+            None => {
+                gst_debug!(self.cat, obj: src, "No front buffer yet");
+                let check = GlCheck::new(gl, task.debug);
+                gl.clear_color(0.0, 0.0, 0.0, 1.0);
+                check.after("glClearColor");
+                gl.clear(gl::COLOR_BUFFER_BIT);
+                return check
+                    .finish("glClear")
+                    .map_err(|err| self.gl_error(src, err));
+            }
+        };
+
+        // Wrap the GStreamer GL context, which is current, so surfman can use it
+        if resources.surfman.is_none() {
+            let surfman = unsafe { Device::from_current_hardware_context() }.map_err(|err| {
+                gst_element_error!(
+                    src,
+                    LibraryError::Failed,
+                    ("Failed to share GL context with surfman"),
+                    ["{:?}", err]
+                );
+                FlowError::Error
+            })?;
+            resources.surfman = Some(surfman);
+        }
+        let (device, context) = resources.surfman.as_mut().unwrap();
 
-        let draw_fbo = gl.gen_framebuffers(1)[0];
-        assert_eq!(gl.get_error(), gl::NO_ERROR);
+        let size = device.surface_info(&surface).size;
+        let surface_texture = device
+            .create_surface_texture(context, surface)
+            .map_err(|err| {
+                gst_element_error!(
+                    src,
+                    LibraryError::Failed,
+                    ("Failed to get front buffer texture"),
+                    ["{:?}", err]
+                );
+                FlowError::Error
+            })?;
 
-        gl.bind_framebuffer(gl::FRAMEBUFFER, draw_fbo);
+        let check = GlCheck::new(gl, task.debug);
+        let read_fbo = *resources
+            .read_fbo
+            .get_or_insert_with(|| gl.gen_framebuffers(1)[0]);
+        check.after("glGenFramebuffers");
+        gl.bind_framebuffer(gl::READ_FRAMEBUFFER, read_fbo);
+        check.after("glBindFramebuffer");
         gl.framebuffer_texture_2d(
-            gl::FRAMEBUFFER,
+            gl::READ_FRAMEBUFFER,
             gl::COLOR_ATTACHMENT0,
-            gl::TEXTURE_2D,
-            draw_texture_id,
+            device.surface_gl_texture_target(),
+            surface_texture.gl_texture(),
+            0,
+        );
+        check.after("glFramebufferTexture2D");
+        // GL memory has the top row first, surfman the bottom row, so we flip
+        gl.blit_framebuffer(
+            0,
+            0,
+            size.width,
+            size.height,
             0,
+            height,
+            width,
+            0,
+            gl::COLOR_BUFFER_BIT,
+            gl::LINEAR,
         );
-        assert_eq!(gl.get_error(), gl::NO_ERROR);
+        check.after("glBlitFramebuffer");
+        gl.framebuffer_texture_2d(
+            gl::READ_FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            device.surface_gl_texture_target(),
+            0,
+            0,
+        );
+        gl.bind_framebuffer(gl::READ_FRAMEBUFFER, 0);
+        let result = check
+            .finish("Blitting the front buffer")
+            .map_err(|err| self.gl_error(src, err));
 
-        gl.clear_color(brightness, brightness, brightness, 1.0);
-        gl.clear(gl::COLOR_BUFFER_BIT);
-        assert_eq!(gl.get_error(), gl::NO_ERROR);
+        // Keep the surface in case the producer doesn't swap before the next frame
+        match device.destroy_surface_texture(context, surface_texture) {
+            Ok(surface) => resources.front_buffer = Some((front_swap_chain, surface)),
+            Err(err) => gst_warning!(self.cat, obj: src, "Lost front buffer: {:?}", err),
+        }
 
-        gl.bind_framebuffer(gl::FRAMEBUFFER, 0);
-        gl.delete_framebuffers(&[draw_fbo]);
-        assert_eq!(gl.get_error(), gl::NO_ERROR);
+        // surfman may have made its own drawable current
+        self.activate(src, gl_context)?;
+        result
+    }
 
-        let frames = self.frames.fetch_add(1, Ordering::SeqCst);
-        if frames % 100 == 0 {
-            let elapsed_micros = self.start.elapsed().as_micros() as u64;
-            let fps = (frames * 1_000_000) / elapsed_micros;
-            gst_info!(self.cat, obj: src, "fps = {}", fps);
+    fn gl_error(&self, src: &BaseSrc, err: GlError) -> FlowError {
+        gst_element_error!(src, LibraryError::Failed, ("GL error"), ["{}", err]);
+        FlowError::Error
+    }
+
+    // Routes KHR_debug messages to our debug category. The callback is installed
+    // every frame, since the context may be shared with elements that install their own.
+    fn enable_debug_output(&self, src: &BaseSrc, gl_context: &GLContext, gl: &Gl) {
+        if !gl_context.check_feature("GL_KHR_debug") {
+            gst_debug!(self.cat, obj: src, "No KHR_debug support");
+            return;
         }
+        let name = if gl_context.get_gl_api().contains(GLAPI::GLES2) {
+            "glDebugMessageCallbackKHR"
+        } else {
+            "glDebugMessageCallback"
+        };
+        let address = gl_context.get_proc_address(name);
+        if address == 0 {
+            gst_debug!(self.cat, obj: src, "No {}", name);
+            return;
+        }
+        unsafe {
+            let debug_message_callback: DebugMessageCallback = mem::transmute(address);
+            debug_message_callback(Some(debug_message), ptr::null());
+        }
+        gl.enable(DEBUG_OUTPUT);
+        gl.enable(DEBUG_OUTPUT_SYNCHRONOUS);
+    }
+}
+
+// KHR_debug isn't in the GL 3.3 bindings, so we declare what we need of it.
+const DEBUG_OUTPUT: gl::GLenum = 0x92E0;
+const DEBUG_OUTPUT_SYNCHRONOUS: gl::GLenum = 0x8242;
+const DEBUG_SEVERITY_HIGH: gl::GLenum = 0x9146;
+const DEBUG_SEVERITY_MEDIUM: gl::GLenum = 0x9147;
+const DEBUG_SEVERITY_LOW: gl::GLenum = 0x9148;
+
+type DebugProc = extern "system" fn(
+    gl::GLenum,
+    gl::GLenum,
+    gl::GLuint,
+    gl::GLenum,
+    gl::GLsizei,
+    *const c_char,
+    *mut c_void,
+);
+type DebugMessageCallback = unsafe extern "system" fn(Option<DebugProc>, *const c_void);
+
+extern "system" fn debug_message(
+    _source: gl::GLenum,
+    _type: gl::GLenum,
+    id: gl::GLuint,
+    severity: gl::GLenum,
+    length: gl::GLsizei,
+    message: *const c_char,
+    _user_param: *mut c_void,
+) {
+    let cat = match DebugCategory::get("myglsrc") {
+        Some(cat) => cat,
+        None => return,
+    };
+    let message = if message.is_null() {
+        Cow::Borrowed("")
+    } else if length < 0 {
+        unsafe { CStr::from_ptr(message) }.to_string_lossy()
+    } else {
+        let bytes = unsafe { slice::from_raw_parts(message as *const u8, length as usize) };
+        String::from_utf8_lossy(bytes)
+    };
+    match severity {
+        DEBUG_SEVERITY_HIGH => gst_error!(cat, "GL message {}: {}", id, message),
+        DEBUG_SEVERITY_MEDIUM => gst_warning!(cat, "GL message {}: {}", id, message),
+        DEBUG_SEVERITY_LOW => gst_info!(cat, "GL message {}: {}", id, message),
+        _ => gst_log!(cat, "GL message {}: {}", id, message),
     }
 }
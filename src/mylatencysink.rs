@@ -0,0 +1,233 @@
+use crate::barcode::read_barcode;
+use crate::formats::FORMATS;
+
+use glib::glib_bool_error;
+use glib::glib_object_impl;
+use glib::glib_object_subclass;
+use glib::subclass::object::ObjectImpl;
+use glib::subclass::simple::ClassStruct;
+use glib::subclass::types::ObjectSubclass;
+use glib::value::ToSendValue;
+use gstreamer::gst_debug;
+use gstreamer::gst_element_error;
+use gstreamer::gst_info;
+use gstreamer::gst_loggable_error;
+use gstreamer::gst_warning;
+use gstreamer::subclass::element::ElementClassSubclassExt;
+use gstreamer::subclass::element::ElementImpl;
+use gstreamer::subclass::ElementInstanceStruct;
+use gstreamer::Buffer;
+use gstreamer::Caps;
+use gstreamer::ClockExt;
+use gstreamer::ClockTime;
+use gstreamer::CoreError;
+use gstreamer::DebugCategory;
+use gstreamer::DebugColorFlags;
+use gstreamer::ElementExt;
+use gstreamer::ErrorMessage;
+use gstreamer::FlowError;
+use gstreamer::FlowSuccess;
+use gstreamer::Fraction;
+use gstreamer::FractionRange;
+use gstreamer::IntRange;
+use gstreamer::List;
+use gstreamer::LoggableError;
+use gstreamer::Message;
+use gstreamer::PadDirection;
+use gstreamer::PadPresence;
+use gstreamer::PadTemplate;
+use gstreamer::Structure;
+use gstreamer_base::subclass::base_sink::BaseSinkImpl;
+use gstreamer_base::BaseSink;
+use gstreamer_video::VideoFrameRef;
+use gstreamer_video::VideoInfo;
+
+use std::sync::Mutex;
+
+// The barcode holds the low 48 bits of the running time in microseconds.
+const BARCODE_MASK: u64 = (1 << 48) - 1;
+
+#[derive(Default)]
+struct State {
+    info: Option<VideoInfo>,
+    frames: u64,
+    missed: u64,
+    min: u64,
+    max: u64,
+    total: u64,
+}
+
+pub struct MyLatencySink {
+    cat: DebugCategory,
+    state: Mutex<Option<State>>,
+}
+
+impl ObjectSubclass for MyLatencySink {
+    const NAME: &'static str = "MyLatencySink";
+    type ParentType = BaseSink;
+    type Instance = ElementInstanceStruct<Self>;
+    type Class = ClassStruct<Self>;
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "mylatencysink",
+                DebugColorFlags::empty(),
+                Some("My latency sink by me"),
+            ),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "My Latency Sink By Me",
+            "Sink/Video",
+            "Reads the barcodes written by mysrc and reports how late each frame is",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+
+        let formats = FORMATS
+            .iter()
+            .map(|format| format.to_string())
+            .collect::<Vec<_>>();
+        let formats = formats
+            .iter()
+            .map(|format| format as &dyn ToSendValue)
+            .collect::<Vec<_>>();
+        let sink_caps = Caps::new_simple(
+            "video/x-raw",
+            &[
+                ("format", &List::new(&formats)),
+                ("width", &IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &IntRange::<i32>::new(1, i32::MAX)),
+                (
+                    "framerate",
+                    &FractionRange::new(Fraction::new(0, 1), Fraction::new(i32::MAX, 1)),
+                ),
+            ],
+        );
+        let sink_pad_template =
+            PadTemplate::new("sink", PadDirection::Sink, PadPresence::Always, &sink_caps).unwrap();
+        klass.add_pad_template(sink_pad_template);
+    }
+
+    glib_object_subclass!();
+}
+
+impl ObjectImpl for MyLatencySink {
+    glib_object_impl!();
+}
+
+impl ElementImpl for MyLatencySink {}
+
+impl BaseSinkImpl for MyLatencySink {
+    fn start(&self, sink: &BaseSink) -> Result<(), ErrorMessage> {
+        *self.state.lock().unwrap() = Some(State::default());
+        gst_info!(self.cat, obj: sink, "Started");
+        Ok(())
+    }
+
+    fn stop(&self, sink: &BaseSink) -> Result<(), ErrorMessage> {
+        if let Some(state) = self.state.lock().unwrap().take() {
+            gst_info!(
+                self.cat,
+                obj: sink,
+                "Stopped after {} frames, {} without a barcode",
+                state.frames,
+                state.missed
+            );
+        }
+        Ok(())
+    }
+
+    fn set_caps(&self, sink: &BaseSink, caps: &Caps) -> Result<(), LoggableError> {
+        let info = VideoInfo::from_caps(caps)
+            .ok_or_else(|| gst_loggable_error!(self.cat, "Failed to get video info"))?;
+        gst_debug!(self.cat, obj: sink, "Configured for caps {}", caps);
+        let mut state_guard = self.state.lock().unwrap();
+        let state = state_guard
+            .as_mut()
+            .ok_or_else(|| gst_loggable_error!(self.cat, "Not started yet"))?;
+        state.info = Some(info);
+        Ok(())
+    }
+
+    fn render(&self, sink: &BaseSink, buffer: &Buffer) -> Result<FlowSuccess, FlowError> {
+        // Buffers are rendered once their running time is reached, so this is when the frame is shown.
+        let now = match sink.get_clock() {
+            Some(clock) => clock.get_time() - sink.get_base_time(),
+            None => {
+                gst_debug!(self.cat, obj: sink, "No clock, can't measure latency");
+                return Ok(FlowSuccess::Ok);
+            }
+        };
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst_element_error!(sink, CoreError::Negotiation, ["Have no state yet"]);
+            FlowError::NotNegotiated
+        })?;
+        let info = state.info.as_ref().ok_or_else(|| {
+            gst_element_error!(sink, CoreError::Negotiation, ["Caps not set yet"]);
+            FlowError::NotNegotiated
+        })?;
+
+        let frame =
+            VideoFrameRef::from_buffer_ref_readable(buffer.as_ref(), info).ok_or_else(|| {
+                gst_element_error!(
+                    sink,
+                    CoreError::Failed,
+                    ["Failed to map input buffer readable"]
+                );
+                FlowError::Error
+            })?;
+        let captured = match read_barcode(&frame) {
+            Some(captured) => captured,
+            None => {
+                gst_warning!(
+                    self.cat,
+                    obj: sink,
+                    "No barcode in frame at {}",
+                    buffer.get_pts()
+                );
+                state.missed += 1;
+                return Ok(FlowSuccess::Ok);
+            }
+        };
+
+        // The barcode wraps around every few years, so compare modulo its range.
+        let now_micros = now.nseconds().unwrap_or(0) / 1000;
+        let latency = now_micros.wrapping_sub(captured) & BARCODE_MASK;
+        state.min = if state.frames == 0 {
+            latency
+        } else {
+            state.min.min(latency)
+        };
+        state.max = state.max.max(latency);
+        state.total += latency;
+        state.frames += 1;
+        gst_debug!(
+            self.cat,
+            obj: sink,
+            "Frame at {} has latency {}us",
+            buffer.get_pts(),
+            latency
+        );
+
+        let micros = ClockTime::from_useconds;
+        let structure = Structure::builder("mylatencysink")
+            .field("running-time", &micros(captured))
+            .field("latency", &micros(latency))
+            .field("min-latency", &micros(state.min))
+            .field("max-latency", &micros(state.max))
+            .field("average-latency", &micros(state.total / state.frames))
+            .field("frames", &state.frames)
+            .field("missed", &state.missed)
+            .build();
+        let message = Message::new_element(structure).src(Some(sink)).build();
+        let _ = sink.post_message(&message);
+
+        Ok(FlowSuccess::Ok)
+    }
+}
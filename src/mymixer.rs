@@ -0,0 +1,859 @@
+use crate::enums::enum_from_value;
+use crate::enums::enum_to_value;
+use crate::enums::register_enum_type;
+
+use glib::glib_bool_error;
+use glib::glib_object_impl;
+use glib::glib_object_subclass;
+use glib::glib_sys::gboolean;
+use glib::glib_sys::GFALSE;
+use glib::glib_sys::GTRUE;
+use glib::object::Cast;
+use glib::subclass::object::ObjectClassSubclassExt;
+use glib::subclass::object::ObjectImpl;
+use glib::subclass::simple::ClassStruct;
+use glib::subclass::simple::InstanceStruct;
+use glib::subclass::types::ObjectSubclass;
+use glib::subclass::Property;
+use glib::translate::from_glib_borrow;
+use glib::ParamFlags;
+use glib::ParamSpec;
+use glib::StaticType;
+use glib::ToValue;
+use glib::Type;
+use glib::Value;
+use gstreamer::gst_debug;
+use gstreamer::gst_element_error;
+use gstreamer::gst_info;
+use gstreamer::gst_loggable_error;
+use gstreamer::subclass::element::ElementClassSubclassExt;
+use gstreamer::subclass::element::ElementImpl;
+use gstreamer::subclass::pad::PadImpl;
+use gstreamer::subclass::ElementInstanceStruct;
+use gstreamer::Buffer;
+use gstreamer::BufferPool;
+use gstreamer::BufferPoolExt;
+use gstreamer::BufferPoolExtManual;
+use gstreamer::BufferRef;
+use gstreamer::Caps;
+use gstreamer::ClockTime;
+use gstreamer::CoreError;
+use gstreamer::DebugCategory;
+use gstreamer::DebugColorFlags;
+use gstreamer::ElementExt;
+use gstreamer::ElementExtManual;
+use gstreamer::ErrorMessage;
+use gstreamer::Event;
+use gstreamer::EventView;
+use gstreamer::FlowError;
+use gstreamer::FlowSuccess;
+use gstreamer::Fraction;
+use gstreamer::FractionRange;
+use gstreamer::IntRange;
+use gstreamer::List;
+use gstreamer::LoggableError;
+use gstreamer::MiniObject;
+use gstreamer::PadDirection;
+use gstreamer::PadExt;
+use gstreamer::PadPresence;
+use gstreamer::PadTemplate;
+use gstreamer::QueryRef;
+use gstreamer::QueryView;
+use gstreamer::SECOND;
+use gstreamer_base::prelude::AggregatorExtManual;
+use gstreamer_base::prelude::AggregatorPadExtManual;
+use gstreamer_base::subclass::aggregator::AggregatorImpl;
+use gstreamer_base::subclass::aggregator::AggregatorImplExt;
+use gstreamer_base::subclass::aggregator_pad::AggregatorPadImpl;
+use gstreamer_base::subclass::aggregator_pad::AggregatorPadImplExt;
+use gstreamer_base::Aggregator;
+use gstreamer_base::AggregatorExt;
+use gstreamer_base::AggregatorPad;
+use gstreamer_base::AggregatorPadExt;
+use gstreamer_base_sys::GstAggregator;
+use gstreamer_base_sys::GstAggregatorClass;
+use gstreamer_sys::GstQuery;
+use gstreamer_video::VideoBufferPool;
+use gstreamer_video::VideoFormat;
+use gstreamer_video::VideoFrameRef;
+use gstreamer_video::VideoInfo;
+use gstreamer_video::VideoMeta;
+use gstreamer_video::BUFFER_POOL_OPTION_VIDEO_META;
+
+use std::sync::Mutex;
+use std::sync::Once;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    Over = 0,
+    Add = 1,
+    Multiply = 2,
+    Screen = 3,
+}
+
+impl BlendMode {
+    fn get_type() -> Type {
+        static ONCE: Once = Once::new();
+        static mut TYPE: Type = Type::Invalid;
+        ONCE.call_once(|| {
+            let type_ = register_enum_type(
+                "MyMixerBlendMode",
+                &[
+                    (BlendMode::Over as i32, "Over", "over"),
+                    (BlendMode::Add as i32, "Add", "add"),
+                    (BlendMode::Multiply as i32, "Multiply", "multiply"),
+                    (BlendMode::Screen as i32, "Screen", "screen"),
+                ],
+            );
+            unsafe { TYPE = type_ };
+        });
+        unsafe { TYPE }
+    }
+
+    fn from_i32(value: i32) -> Option<BlendMode> {
+        match value {
+            0 => Some(BlendMode::Over),
+            1 => Some(BlendMode::Add),
+            2 => Some(BlendMode::Multiply),
+            3 => Some(BlendMode::Screen),
+            _ => None,
+        }
+    }
+
+    // Blends one channel, with everything scaled to 0..1.
+    fn blend(self, src: f32, dst: f32, alpha: f32) -> f32 {
+        let blended = match self {
+            BlendMode::Over => src,
+            BlendMode::Add => (dst + src).min(1.0),
+            BlendMode::Multiply => dst * src,
+            BlendMode::Screen => 1.0 - (1.0 - dst) * (1.0 - src),
+        };
+        blended * alpha + dst * (1.0 - alpha)
+    }
+}
+
+static PAD_PROPERTIES: [Property; 7] = [
+    Property("xpos", |name| {
+        ParamSpec::int(
+            name,
+            "X position",
+            "Horizontal position of the input in the output",
+            i32::MIN,
+            i32::MAX,
+            0,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("ypos", |name| {
+        ParamSpec::int(
+            name,
+            "Y position",
+            "Vertical position of the input in the output",
+            i32::MIN,
+            i32::MAX,
+            0,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("width", |name| {
+        ParamSpec::int(
+            name,
+            "Width",
+            "Width of the input in the output (0 for the input width)",
+            0,
+            i32::MAX,
+            0,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("height", |name| {
+        ParamSpec::int(
+            name,
+            "Height",
+            "Height of the input in the output (0 for the input height)",
+            0,
+            i32::MAX,
+            0,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("alpha", |name| {
+        ParamSpec::double(
+            name,
+            "Alpha",
+            "Opacity of the input",
+            0.0,
+            1.0,
+            1.0,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("zorder", |name| {
+        ParamSpec::uint(
+            name,
+            "Z-order",
+            "Stacking order of the input, higher is on top",
+            0,
+            u32::MAX,
+            0,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("operator", |name| {
+        ParamSpec::enum_(
+            name,
+            "Operator",
+            "How the input is blended onto the inputs below it",
+            BlendMode::get_type(),
+            BlendMode::Over as i32,
+            ParamFlags::READWRITE,
+        )
+    }),
+];
+
+#[derive(Clone, Copy)]
+struct PadSettings {
+    xpos: i32,
+    ypos: i32,
+    width: i32,
+    height: i32,
+    alpha: f64,
+    zorder: u32,
+    operator: BlendMode,
+}
+
+impl Default for PadSettings {
+    fn default() -> Self {
+        Self {
+            xpos: 0,
+            ypos: 0,
+            width: 0,
+            height: 0,
+            alpha: 1.0,
+            zorder: 0,
+            operator: BlendMode::Over,
+        }
+    }
+}
+
+pub struct MyMixerPad {
+    settings: Mutex<PadSettings>,
+    info: Mutex<Option<VideoInfo>>,
+    // The most recent buffer, which is repeated until a newer one arrives,
+    // with the info it was negotiated with, since the caps may have changed since.
+    current: Mutex<Option<(Buffer, VideoInfo)>>,
+}
+
+impl ObjectSubclass for MyMixerPad {
+    const NAME: &'static str = "MyMixerPad";
+    type ParentType = AggregatorPad;
+    type Instance = InstanceStruct<Self>;
+    type Class = ClassStruct<Self>;
+
+    fn new() -> Self {
+        Self {
+            settings: Mutex::new(PadSettings::default()),
+            info: Mutex::new(None),
+            current: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.install_properties(&PAD_PROPERTIES);
+    }
+
+    glib_object_subclass!();
+}
+
+impl ObjectImpl for MyMixerPad {
+    glib_object_impl!();
+
+    fn set_property(&self, _obj: &glib::Object, id: usize, value: &Value) {
+        let mut settings = self.settings.lock().unwrap();
+        match PAD_PROPERTIES[id] {
+            Property("xpos", ..) => settings.xpos = value.get().unwrap(),
+            Property("ypos", ..) => settings.ypos = value.get().unwrap(),
+            Property("width", ..) => settings.width = value.get().unwrap(),
+            Property("height", ..) => settings.height = value.get().unwrap(),
+            Property("alpha", ..) => settings.alpha = value.get().unwrap(),
+            Property("zorder", ..) => settings.zorder = value.get().unwrap(),
+            Property("operator", ..) => {
+                settings.operator = enum_from_value(value)
+                    .and_then(BlendMode::from_i32)
+                    .unwrap_or(BlendMode::Over)
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: usize) -> Result<Value, ()> {
+        let settings = self.settings.lock().unwrap();
+        match PAD_PROPERTIES[id] {
+            Property("xpos", ..) => Ok(settings.xpos.to_value()),
+            Property("ypos", ..) => Ok(settings.ypos.to_value()),
+            Property("width", ..) => Ok(settings.width.to_value()),
+            Property("height", ..) => Ok(settings.height.to_value()),
+            Property("alpha", ..) => Ok(settings.alpha.to_value()),
+            Property("zorder", ..) => Ok(settings.zorder.to_value()),
+            Property("operator", ..) => Ok(enum_to_value(
+                BlendMode::get_type(),
+                settings.operator as i32,
+            )),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl PadImpl for MyMixerPad {}
+
+impl AggregatorPadImpl for MyMixerPad {
+    fn flush(
+        &self,
+        pad: &AggregatorPad,
+        aggregator: &Aggregator,
+    ) -> Result<FlowSuccess, FlowError> {
+        let _ = self.current.lock().unwrap().take();
+        self.parent_flush(pad, aggregator)
+    }
+}
+
+impl MyMixerPad {
+    // The size the input is drawn at in the output.
+    fn output_size(&self, settings: &PadSettings) -> Option<(u32, u32)> {
+        let info_guard = self.info.lock().unwrap();
+        let info = info_guard.as_ref()?;
+        let width = if settings.width > 0 {
+            settings.width as u32
+        } else {
+            info.width()
+        };
+        let height = if settings.height > 0 {
+            settings.height as u32
+        } else {
+            info.height()
+        };
+        Some((width, height))
+    }
+}
+
+struct State {
+    out_info: VideoInfo,
+    // The segment time of the first output frame.
+    base: ClockTime,
+    frames: u64,
+}
+
+impl State {
+    fn frame_time(&self, frames: u64) -> ClockTime {
+        let fps = self.out_info.fps();
+        let nanos =
+            frames * SECOND.nseconds().unwrap() * (*fps.denom() as u64) / (*fps.numer() as u64);
+        self.base + ClockTime::from_nseconds(nanos)
+    }
+}
+
+pub struct MyMixer {
+    cat: DebugCategory,
+    state: Mutex<Option<State>>,
+}
+
+impl ObjectSubclass for MyMixer {
+    const NAME: &'static str = "MyMixer";
+    type ParentType = Aggregator;
+    type Instance = ElementInstanceStruct<Self>;
+    type Class = ClassStruct<Self>;
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new("mymixer", DebugColorFlags::empty(), Some("My mixer by me")),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "My Mixer By Me",
+            "Filter/Editor/Video/Compositor",
+            "Blends video streams together",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+
+        let formats = List::new(&[
+            &VideoFormat::Bgra.to_string(),
+            &VideoFormat::Bgrx.to_string(),
+        ]);
+
+        let src_caps = Caps::new_simple(
+            "video/x-raw",
+            &[
+                ("format", &formats),
+                ("width", &IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &IntRange::<i32>::new(1, i32::MAX)),
+                (
+                    "framerate",
+                    &FractionRange::new(Fraction::new(1, i32::MAX), Fraction::new(i32::MAX, 1)),
+                ),
+            ],
+        );
+        let src_pad_template = PadTemplate::new_with_gtype(
+            "src",
+            PadDirection::Src,
+            PadPresence::Always,
+            &src_caps,
+            AggregatorPad::static_type(),
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        let sink_caps = Caps::new_simple(
+            "video/x-raw",
+            &[
+                ("format", &formats),
+                ("width", &IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &IntRange::<i32>::new(1, i32::MAX)),
+                (
+                    "framerate",
+                    &FractionRange::new(Fraction::new(0, 1), Fraction::new(i32::MAX, 1)),
+                ),
+            ],
+        );
+        let sink_pad_template = PadTemplate::new_with_gtype(
+            "sink_%u",
+            PadDirection::Sink,
+            PadPresence::Request,
+            &sink_caps,
+            MyMixerPad::get_type(),
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+
+        // AggregatorImpl doesn't have decide_allocation, so we override it ourselves
+        unsafe {
+            let aggregator_class =
+                &mut *(klass as *mut ClassStruct<Self> as *mut GstAggregatorClass);
+            aggregator_class.decide_allocation = Some(decide_allocation_trampoline);
+        }
+    }
+
+    glib_object_subclass!();
+}
+
+impl ObjectImpl for MyMixer {
+    glib_object_impl!();
+}
+
+impl ElementImpl for MyMixer {}
+
+impl AggregatorImpl for MyMixer {
+    fn start(&self, aggregator: &Aggregator) -> Result<(), ErrorMessage> {
+        let _ = self.state.lock().unwrap().take();
+        self.parent_start(aggregator)
+    }
+
+    fn stop(&self, aggregator: &Aggregator) -> Result<(), ErrorMessage> {
+        let _ = self.state.lock().unwrap().take();
+        for pad in self.mixer_pads(aggregator) {
+            let _ = MyMixerPad::from_instance(&pad)
+                .current
+                .lock()
+                .unwrap()
+                .take();
+        }
+        gst_info!(self.cat, obj: aggregator, "Stopped");
+        self.parent_stop(aggregator)
+    }
+
+    fn flush(&self, aggregator: &Aggregator) -> Result<FlowSuccess, FlowError> {
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.base = ClockTime::none();
+            state.frames = 0;
+        }
+        self.parent_flush(aggregator)
+    }
+
+    fn sink_event(&self, aggregator: &Aggregator, pad: &AggregatorPad, event: Event) -> bool {
+        if let EventView::Caps(caps) = event.view() {
+            let caps = caps.get_caps();
+            match VideoInfo::from_caps(caps) {
+                Some(info) => {
+                    gst_debug!(self.cat, obj: pad, "Configured for caps {}", caps);
+                    *MyMixerPad::from_instance(pad).info.lock().unwrap() = Some(info);
+                    if let Some(src_pad) = aggregator.get_static_pad("src") {
+                        src_pad.mark_reconfigure();
+                    }
+                }
+                None => {
+                    gst_element_error!(
+                        aggregator,
+                        CoreError::Negotiation,
+                        ["Failed to get video info from {}", caps]
+                    );
+                    return false;
+                }
+            }
+        }
+        self.parent_sink_event(aggregator, pad, event)
+    }
+
+    fn fixate_src_caps(&self, aggregator: &Aggregator, caps: Caps) -> Caps {
+        // By default the output is just big enough to fit all the inputs,
+        // at the fastest input framerate.
+        let mut width = 1;
+        let mut height = 1;
+        let mut framerate = Fraction::new(0, 1);
+        for pad in self.mixer_pads(aggregator) {
+            let mixer_pad = MyMixerPad::from_instance(&pad);
+            let settings = *mixer_pad.settings.lock().unwrap();
+            if let Some((pad_width, pad_height)) = mixer_pad.output_size(&settings) {
+                width = width.max(pad_extent(settings.xpos, pad_width));
+                height = height.max(pad_extent(settings.ypos, pad_height));
+            }
+            if let Some(ref info) = *mixer_pad.info.lock().unwrap() {
+                if info.fps() > framerate {
+                    framerate = info.fps();
+                }
+            }
+        }
+        if *framerate.numer() == 0 {
+            framerate = Fraction::new(30, 1);
+        }
+
+        let mut caps = caps;
+        {
+            let caps = caps.make_mut();
+            let s = caps.get_mut_structure(0).unwrap();
+            s.fixate_field_nearest_int("width", width);
+            s.fixate_field_nearest_int("height", height);
+            s.fixate_field_nearest_fraction("framerate", framerate);
+        }
+        let caps = Caps::fixate(caps);
+        gst_debug!(self.cat, obj: aggregator, "Fixated caps to {}", caps);
+        caps
+    }
+
+    fn negotiated_src_caps(
+        &self,
+        aggregator: &Aggregator,
+        caps: &Caps,
+    ) -> Result<(), LoggableError> {
+        let out_info = VideoInfo::from_caps(caps)
+            .ok_or_else(|| gst_loggable_error!(self.cat, "Failed to get video info"))?;
+        if *out_info.fps().numer() == 0 {
+            return Err(gst_loggable_error!(
+                self.cat,
+                "Variable framerate not supported"
+            ));
+        }
+        gst_debug!(self.cat, obj: aggregator, "Configured for caps {}", caps);
+
+        // An output frame mixes every input that starts before it ends, so in live
+        // pipelines it can't be produced until its end, a frame after its start.
+        let fps = out_info.fps();
+        let frame_duration = SECOND
+            .nseconds()
+            .map(|nanos| nanos * (*fps.denom() as u64) / (*fps.numer() as u64))
+            .map(ClockTime::from_nseconds)
+            .unwrap_or_else(ClockTime::none);
+        aggregator.set_latency(frame_duration, frame_duration);
+
+        let mut state = self.state.lock().unwrap();
+        let (base, frames) = match *state {
+            Some(ref state) => (state.frame_time(state.frames), 0),
+            None => (ClockTime::none(), 0),
+        };
+        *state = Some(State {
+            out_info,
+            base,
+            frames,
+        });
+        Ok(())
+    }
+
+    fn get_next_time(&self, aggregator: &Aggregator) -> ClockTime {
+        let state_guard = self.state.lock().unwrap();
+        let state = match *state_guard {
+            Some(ref state) => state,
+            None => return ClockTime::none(),
+        };
+        let src_segment = self.src_segment(aggregator);
+        let next_time = if state.base.is_some() {
+            state.frame_time(state.frames)
+        } else {
+            src_segment.get_start()
+        };
+        src_segment.to_running_time(next_time)
+    }
+
+    fn aggregate(&self, aggregator: &Aggregator, timeout: bool) -> Result<FlowSuccess, FlowError> {
+        let src_segment = self.src_segment(aggregator);
+        let mut state_guard = self.state.lock().unwrap();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst_element_error!(aggregator, CoreError::Negotiation, ["Caps not set yet"]);
+            FlowError::NotNegotiated
+        })?;
+        if state.base.is_none() {
+            state.base = src_segment.get_start();
+        }
+
+        let start = state.frame_time(state.frames);
+        let end = state.frame_time(state.frames + 1);
+        let start_running_time = src_segment.to_running_time(start);
+        let end_running_time = src_segment.to_running_time(end);
+
+        // Collect the latest buffer for each input that starts before this
+        // output frame ends. Inputs which are late just repeat their last frame.
+        let mut all_eos = true;
+        let mut inputs = Vec::new();
+        for pad in self.mixer_pads(aggregator) {
+            let mixer_pad = MyMixerPad::from_instance(&pad);
+            let segment = pad.get_segment();
+            let segment = segment.downcast_ref::<ClockTime>().unwrap();
+            let mut current = mixer_pad.current.lock().unwrap();
+            while let Some(buffer) = pad.peek_buffer() {
+                let buffer_running_time = segment.to_running_time(buffer.get_pts());
+                if buffer_running_time.is_some() && buffer_running_time >= end_running_time {
+                    break;
+                }
+                let info = mixer_pad.info.lock().unwrap().clone();
+                *current = pad.pop_buffer().and_then(|buffer| Some((buffer, info?)));
+            }
+            if !pad.is_eos() || pad.has_buffer() {
+                all_eos = false;
+            }
+            if let Some((ref buffer, ref info)) = *current {
+                let settings = *mixer_pad.settings.lock().unwrap();
+                inputs.push((settings, info.clone(), buffer.clone()));
+            }
+        }
+
+        if all_eos {
+            gst_debug!(self.cat, obj: aggregator, "All inputs are EOS");
+            return Err(FlowError::Eos);
+        }
+
+        gst_debug!(
+            self.cat,
+            obj: aggregator,
+            "Mixing {} inputs at {} (running time {}, timeout {})",
+            inputs.len(),
+            start,
+            start_running_time,
+            timeout
+        );
+
+        inputs.sort_by_key(|(settings, _, _)| settings.zorder);
+
+        let mut outbuf = self.acquire_buffer(aggregator)?;
+        {
+            let outbuf = outbuf.get_mut().unwrap();
+            outbuf.set_pts(start);
+            outbuf.set_duration(end - start);
+            self.mix(aggregator, &state.out_info, &inputs, outbuf)?;
+        }
+        state.frames += 1;
+        drop(state_guard);
+
+        aggregator.finish_buffer(outbuf)
+    }
+}
+
+impl MyMixer {
+    // Makes sure the query has a video pool for the negotiated caps, using
+    // downstream's if it offered one.
+    fn decide_allocation(
+        &self,
+        aggregator: &Aggregator,
+        query: &mut QueryRef,
+    ) -> Result<(), LoggableError> {
+        let mut q = match query.view_mut() {
+            QueryView::Allocation(q) => q,
+            _ => return Err(gst_loggable_error!(self.cat, "Not an allocation query")),
+        };
+        let outcaps = q.get_owned().0;
+        let out_info = VideoInfo::from_caps(&outcaps)
+            .ok_or_else(|| gst_loggable_error!(self.cat, "Failed to get video info"))?;
+        let video_meta = q.find_allocation_meta::<VideoMeta>().is_some();
+        let proposed = q.get_allocation_pools().into_iter().next();
+        let offered = proposed.is_some();
+        let (pool, size, min_buffers, max_buffers) = match proposed {
+            Some((Some(pool), size, min_buffers, max_buffers)) => {
+                (pool, size, min_buffers, max_buffers)
+            }
+            Some((None, size, min_buffers, max_buffers)) => (
+                VideoBufferPool::new().upcast::<BufferPool>(),
+                size,
+                min_buffers,
+                max_buffers,
+            ),
+            None => (VideoBufferPool::new().upcast::<BufferPool>(), 0, 0, 0),
+        };
+        let size = size.max(out_info.size() as u32);
+
+        let mut config = pool.get_config();
+        config.set_params(Some(&outcaps), size, min_buffers, max_buffers);
+        if video_meta {
+            config.add_option(&BUFFER_POOL_OPTION_VIDEO_META);
+        }
+        pool.set_config(config)
+            .map_err(|_| gst_loggable_error!(self.cat, "Failed to update config"))?;
+        gst_debug!(
+            self.cat,
+            obj: aggregator,
+            "Allocating from a pool with{} video meta",
+            if video_meta { "" } else { "out" }
+        );
+
+        // Aggregator activates the first pool, and hands it out from get_buffer_pool
+        if offered {
+            q.set_nth_allocation_pool(0, Some(&pool), size, min_buffers, max_buffers);
+        } else {
+            q.add_allocation_pool(Some(&pool), size, min_buffers, max_buffers);
+        }
+        Ok(())
+    }
+
+    fn acquire_buffer(&self, aggregator: &Aggregator) -> Result<Buffer, FlowError> {
+        let pool = aggregator.get_buffer_pool().ok_or_else(|| {
+            gst_element_error!(aggregator, CoreError::Negotiation, ["Have no buffer pool"]);
+            FlowError::NotNegotiated
+        })?;
+        if !pool.is_active() {
+            pool.set_active(true).map_err(|_| FlowError::Error)?;
+        }
+        pool.acquire_buffer(None)
+    }
+
+    fn mixer_pads(&self, aggregator: &Aggregator) -> Vec<AggregatorPad> {
+        aggregator
+            .get_sink_pads()
+            .into_iter()
+            .filter_map(|pad| pad.downcast::<AggregatorPad>().ok())
+            .collect()
+    }
+
+    fn src_segment(&self, aggregator: &Aggregator) -> gstreamer::FormattedSegment<ClockTime> {
+        aggregator
+            .get_static_pad("src")
+            .and_then(|pad| pad.downcast::<AggregatorPad>().ok())
+            .and_then(|pad| pad.get_segment().downcast::<ClockTime>().ok())
+            .unwrap_or_default()
+    }
+
+    fn mix(
+        &self,
+        aggregator: &Aggregator,
+        out_info: &VideoInfo,
+        inputs: &[(PadSettings, VideoInfo, Buffer)],
+        outbuf: &mut BufferRef,
+    ) -> Result<(), FlowError> {
+        let mut out_frame =
+            VideoFrameRef::from_buffer_ref_writable(outbuf, out_info).ok_or_else(|| {
+                gst_element_error!(
+                    aggregator,
+                    CoreError::Failed,
+                    ["Failed to map output buffer writable"]
+                );
+                FlowError::Error
+            })?;
+        let out_width = out_frame.width() as i64;
+        let out_height = out_frame.height() as i64;
+        let out_stride = out_frame.plane_stride()[0] as usize;
+        let out_has_alpha = out_frame.format() == VideoFormat::Bgra;
+        let out_data = out_frame.plane_data_mut(0).unwrap();
+
+        // Opaque black background
+        for line in out_data.chunks_exact_mut(out_stride) {
+            for pixel in line[..out_width as usize * 4].chunks_exact_mut(4) {
+                pixel[0] = 0;
+                pixel[1] = 0;
+                pixel[2] = 0;
+                pixel[3] = if out_has_alpha { 255 } else { 0 };
+            }
+        }
+
+        for (settings, in_info, inbuf) in inputs {
+            let in_frame = VideoFrameRef::from_buffer_ref_readable(inbuf.as_ref(), in_info)
+                .ok_or_else(|| {
+                    gst_element_error!(
+                        aggregator,
+                        CoreError::Failed,
+                        ["Failed to map input buffer readable"]
+                    );
+                    FlowError::Error
+                })?;
+            let in_width = in_frame.width() as i64;
+            let in_height = in_frame.height() as i64;
+            let in_stride = in_frame.plane_stride()[0] as usize;
+            let in_has_alpha = in_frame.format() == VideoFormat::Bgra;
+            let in_data = in_frame.plane_data(0).unwrap();
+
+            let width = if settings.width > 0 {
+                settings.width as i64
+            } else {
+                in_width
+            };
+            let height = if settings.height > 0 {
+                settings.height as i64
+            } else {
+                in_height
+            };
+            let xpos = settings.xpos as i64;
+            let ypos = settings.ypos as i64;
+            let alpha = settings.alpha as f32;
+            if alpha <= 0.0 {
+                continue;
+            }
+
+            // Nearest-neighbour scaling, clipped to the output frame.
+            for y in ypos.max(0)..(ypos + height).min(out_height) {
+                let in_y = ((y - ypos) * in_height / height) as usize;
+                let in_line = &in_data[in_y * in_stride..];
+                let out_line = &mut out_data[y as usize * out_stride..];
+                for x in xpos.max(0)..(xpos + width).min(out_width) {
+                    let in_x = ((x - xpos) * in_width / width) as usize;
+                    let in_p = &in_line[in_x * 4..in_x * 4 + 4];
+                    let out_p = &mut out_line[x as usize * 4..x as usize * 4 + 4];
+                    let pixel_alpha = if in_has_alpha {
+                        alpha * in_p[3] as f32 / 255.0
+                    } else {
+                        alpha
+                    };
+                    for c in 0..3 {
+                        let src = in_p[c] as f32 / 255.0;
+                        let dst = out_p[c] as f32 / 255.0;
+                        let blended = settings.operator.blend(src, dst, pixel_alpha);
+                        out_p[c] = (blended * 255.0 + 0.5) as u8;
+                    }
+                    if out_has_alpha {
+                        let dst_alpha = out_p[3] as f32 / 255.0;
+                        let blended = pixel_alpha + dst_alpha * (1.0 - pixel_alpha);
+                        out_p[3] = (blended * 255.0 + 0.5) as u8;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+unsafe extern "C" fn decide_allocation_trampoline(
+    ptr: *mut GstAggregator,
+    query: *mut GstQuery,
+) -> gboolean {
+    let aggregator: Aggregator = from_glib_borrow(ptr);
+    let imp = MyMixer::from_instance(&aggregator);
+    let query = QueryRef::from_mut_ptr(query);
+    if let Err(err) = imp.decide_allocation(&aggregator, query) {
+        err.log_with_object(&aggregator);
+        return GFALSE;
+    }
+    GTRUE
+}
+
+// How far a pad reaches into the output, clamped to the largest caps size.
+fn pad_extent(position: i32, size: u32) -> i32 {
+    i64::from(position.max(0))
+        .saturating_add(i64::from(size))
+        .min(i64::from(i32::MAX)) as i32
+}
@@ -1,42 +1,264 @@
+use crate::clockwait::ClockWait;
+use crate::enums::enum_from_value;
+use crate::enums::enum_to_value;
+use crate::formats::paint_frame;
+use crate::formats::FORMATS;
+use crate::image::sequence_paths;
+use crate::image::Image;
+use crate::image::ImageError;
+use crate::pattern::Color;
+use crate::pattern::Paint;
+use crate::pattern::Pattern;
+use crate::pattern::PatternParams;
+use crate::sync::has_sync_point;
+use crate::sync::Flash;
+use crate::timing::frame_at;
+use crate::timing::frame_time;
+use crate::timing::skip_missed;
+
 use glib::glib_bool_error;
 use glib::glib_object_impl;
 use glib::glib_object_subclass;
+use glib::glib_sys::gboolean;
+use glib::glib_sys::GFALSE;
+use glib::glib_sys::GTRUE;
+use glib::object::Cast;
+use glib::subclass::object::ObjectClassSubclassExt;
 use glib::subclass::object::ObjectImpl;
+use glib::subclass::object::ObjectImplExt;
 use glib::subclass::simple::ClassStruct;
+use glib::subclass::types::InstanceStruct;
 use glib::subclass::types::ObjectSubclass;
+use glib::subclass::Property;
+use glib::translate::from_glib_borrow;
+use glib::value::ToSendValue;
+use glib::ParamFlags;
+use glib::ParamSpec;
+use glib::ToValue;
+use glib::Value;
+use gstreamer::format::Default as DefaultFormat;
 use gstreamer::gst_debug;
 use gstreamer::gst_element_error;
+use gstreamer::gst_error;
+use gstreamer::gst_error_msg;
+use gstreamer::gst_info;
 use gstreamer::gst_loggable_error;
 use gstreamer::subclass::element::ElementClassSubclassExt;
 use gstreamer::subclass::element::ElementImpl;
 use gstreamer::subclass::ElementInstanceStruct;
-use gstreamer::BufferRef;
+use gstreamer::Buffer;
+use gstreamer::BufferFlags;
+use gstreamer::BufferPool;
+use gstreamer::BufferPoolExt;
+use gstreamer::BufferPoolExtManual;
 use gstreamer::Caps;
+use gstreamer::ClockTime;
 use gstreamer::CoreError;
 use gstreamer::DebugCategory;
 use gstreamer::DebugColorFlags;
+use gstreamer::ErrorMessage;
 use gstreamer::FlowError;
-use gstreamer::FlowSuccess;
+use gstreamer::Format;
 use gstreamer::Fraction;
 use gstreamer::FractionRange;
+use gstreamer::GenericFormattedValue;
 use gstreamer::IntRange;
+use gstreamer::List;
 use gstreamer::LoggableError;
+use gstreamer::MiniObject;
 use gstreamer::PadDirection;
 use gstreamer::PadPresence;
 use gstreamer::PadTemplate;
+use gstreamer::QueryRef;
+use gstreamer::QueryView;
+use gstreamer::ResourceError;
+use gstreamer::Segment;
+use gstreamer::StreamError;
+use gstreamer_base::prelude::BaseSrcExtManual;
 use gstreamer_base::subclass::base_src::BaseSrcImpl;
+use gstreamer_base::subclass::base_src::BaseSrcImplExt;
 use gstreamer_base::BaseSrc;
-use gstreamer_video::VideoFormat;
+use gstreamer_base::BaseSrcExt;
+use gstreamer_base_sys::GstBaseSrc;
+use gstreamer_base_sys::GstBaseSrcClass;
+use gstreamer_sys::GstQuery;
+use gstreamer_video::prelude::VideoBufferPoolConfig;
+use gstreamer_video::VideoAlignment;
+use gstreamer_video::VideoBufferPool;
 use gstreamer_video::VideoFrameRef;
 use gstreamer_video::VideoInfo;
+use gstreamer_video::VideoMeta;
+use gstreamer_video::BUFFER_POOL_OPTION_VIDEO_ALIGNMENT;
+use gstreamer_video::BUFFER_POOL_OPTION_VIDEO_META;
 
+use std::io;
 use std::sync::Mutex;
-use std::time::Instant;
+use std::time::Duration;
+
+const DEFAULT_WIDTH: i32 = 1280;
+const DEFAULT_HEIGHT: i32 = 720;
+const DEFAULT_FPS: i32 = 30;
+const DEFAULT_IMAGE_DURATION: u64 = 1_000_000_000;
+
+// Strides are aligned to 32 bytes when downstream lets us choose them.
+const STRIDE_ALIGN: u32 = 31;
+
+static PROPERTIES: [Property; 10] = [
+    Property("pattern", |name| {
+        ParamSpec::enum_(
+            name,
+            "Pattern",
+            "Type of test pattern to generate",
+            Pattern::get_type(),
+            Pattern::Pulse as i32,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("foreground-color", |name| {
+        ParamSpec::uint(
+            name,
+            "Foreground colour",
+            "Colour of the solid-color pattern, as 0xAARRGGBB",
+            0,
+            u32::MAX,
+            0xffff_ffff,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("seed", |name| {
+        ParamSpec::uint(
+            name,
+            "Seed",
+            "Seed for the snow pattern",
+            0,
+            u32::MAX,
+            0,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("is-live", |name| {
+        ParamSpec::boolean(
+            name,
+            "Is live",
+            "Whether to act as a live source",
+            false,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("barcode", |name| {
+        ParamSpec::boolean(
+            name,
+            "Barcode",
+            "Whether to encode the running time of each frame as a barcode, for mylatencysink",
+            false,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("location", |name| {
+        ParamSpec::string(
+            name,
+            "Location",
+            "PNG or PPM image to show instead of the pattern, or a numbered sequence such as frame%05d.png",
+            None,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("image-duration", |name| {
+        ParamSpec::uint64(
+            name,
+            "Image duration",
+            "How long to show each image for, in nanoseconds",
+            1,
+            u64::MAX,
+            DEFAULT_IMAGE_DURATION,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("loop", |name| {
+        ParamSpec::boolean(
+            name,
+            "Loop",
+            "Whether to go back to the first image after the last one",
+            false,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("flash", |name| {
+        ParamSpec::boolean(
+            name,
+            "Flash",
+            "Whether to flash white at the start of every second, in time with myaudiosrc's beeps",
+            false,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("cache", |name| {
+        ParamSpec::boolean(
+            name,
+            "Cache",
+            "Whether to paint static patterns and images once, and share the memory between frames",
+            false,
+            ParamFlags::READWRITE,
+        )
+    }),
+];
+
+#[derive(Clone)]
+struct Settings {
+    pattern: Pattern,
+    foreground_color: u32,
+    seed: u32,
+    is_live: bool,
+    barcode: bool,
+    location: Option<String>,
+    image_duration: u64,
+    looping: bool,
+    flash: bool,
+    cache: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            pattern: Pattern::Pulse,
+            foreground_color: 0xffff_ffff,
+            seed: 0,
+            is_live: false,
+            barcode: false,
+            location: None,
+            image_duration: DEFAULT_IMAGE_DURATION,
+            looping: false,
+            flash: false,
+            cache: false,
+        }
+    }
+}
+
+// The images of a slideshow, and the one being shown.
+struct Slideshow {
+    paths: Vec<String>,
+    current: Option<(usize, Image)>,
+}
+
+// Where we are in the stream. After a seek the frame is worked out from the segment
+// when the next buffer is filled, since the framerate may not be known yet.
+#[derive(Clone, Copy, Debug)]
+enum Position {
+    Seeked,
+    Frame(u64),
+    Done,
+}
 
 pub struct MySrc {
     cat: DebugCategory,
-    start: Instant,
+    position: Mutex<Position>,
+    settings: Mutex<Settings>,
     out_info: Mutex<Option<VideoInfo>>,
+    clock_wait: ClockWait,
+    slideshow: Mutex<Option<Slideshow>>,
+    buffer_pool: Mutex<Option<BufferPool>>,
+    // A static frame, and the image it shows if any.
+    cache: Mutex<Option<(Option<usize>, Buffer)>>,
 }
 
 impl ObjectSubclass for MySrc {
@@ -48,8 +270,13 @@ impl ObjectSubclass for MySrc {
     fn new() -> Self {
         Self {
             cat: DebugCategory::new("mysrc", DebugColorFlags::empty(), Some("My src by me")),
-            start: Instant::now(),
+            position: Mutex::new(Position::Seeked),
+            settings: Mutex::new(Settings::default()),
             out_info: Mutex::new(None),
+            clock_wait: ClockWait::default(),
+            slideshow: Mutex::new(None),
+            buffer_pool: Mutex::new(None),
+            cache: Mutex::new(None),
         }
     }
 
@@ -61,21 +288,37 @@ impl ObjectSubclass for MySrc {
             env!("CARGO_PKG_AUTHORS"),
         );
 
+        let formats = FORMATS
+            .iter()
+            .map(|format| format.to_string())
+            .collect::<Vec<_>>();
+        let formats = formats
+            .iter()
+            .map(|format| format as &dyn ToSendValue)
+            .collect::<Vec<_>>();
         let src_caps = Caps::new_simple(
             "video/x-raw",
             &[
-                ("format", &VideoFormat::Bgrx.to_string()),
-                ("width", &IntRange::<i32>::new(512, 1024)),
-                ("height", &IntRange::<i32>::new(512, 1024)),
+                ("format", &List::new(&formats)),
+                ("width", &IntRange::<i32>::new(1, i32::MAX)),
+                ("height", &IntRange::<i32>::new(1, i32::MAX)),
                 (
                     "framerate",
-                    &FractionRange::new(Fraction::new(25, 1), Fraction::new(120, 1)),
+                    &FractionRange::new(Fraction::new(0, 1), Fraction::new(i32::MAX, 1)),
                 ),
             ],
         );
         let src_pad_template =
             PadTemplate::new("src", PadDirection::Src, PadPresence::Always, &src_caps).unwrap();
         klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+
+        // BaseSrcImpl doesn't have decide_allocation, so we override it ourselves
+        unsafe {
+            let base_src_class = &mut *(klass as *mut ClassStruct<Self> as *mut GstBaseSrcClass);
+            base_src_class.decide_allocation = Some(decide_allocation_trampoline);
+        }
     }
 
     glib_object_subclass!();
@@ -83,26 +326,206 @@ impl ObjectSubclass for MySrc {
 
 impl ObjectImpl for MySrc {
     glib_object_impl!();
+
+    fn constructed(&self, obj: &glib::Object) {
+        self.parent_constructed(obj);
+        let basesrc = obj.downcast_ref::<BaseSrc>().unwrap();
+        basesrc.set_format(Format::Time);
+    }
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &Value) {
+        let mut settings = self.settings.lock().unwrap();
+        match PROPERTIES[id] {
+            Property("pattern", ..) => {
+                settings.pattern = enum_from_value(value)
+                    .and_then(Pattern::from_i32)
+                    .unwrap_or(Pattern::Pulse)
+            }
+            Property("foreground-color", ..) => settings.foreground_color = value.get().unwrap(),
+            Property("seed", ..) => settings.seed = value.get().unwrap(),
+            Property("is-live", ..) => {
+                settings.is_live = value.get().unwrap();
+                let basesrc = obj.downcast_ref::<BaseSrc>().unwrap();
+                basesrc.set_live(settings.is_live);
+            }
+            Property("barcode", ..) => settings.barcode = value.get().unwrap(),
+            Property("location", ..) => settings.location = value.get(),
+            Property("image-duration", ..) => settings.image_duration = value.get().unwrap(),
+            Property("loop", ..) => settings.looping = value.get().unwrap(),
+            Property("flash", ..) => settings.flash = value.get().unwrap(),
+            Property("cache", ..) => settings.cache = value.get().unwrap(),
+            _ => unimplemented!(),
+        }
+        // Whatever changed, the cached frame may be out of date.
+        *self.cache.lock().unwrap() = None;
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: usize) -> Result<Value, ()> {
+        let settings = self.settings.lock().unwrap();
+        match PROPERTIES[id] {
+            Property("pattern", ..) => {
+                Ok(enum_to_value(Pattern::get_type(), settings.pattern as i32))
+            }
+            Property("foreground-color", ..) => Ok(settings.foreground_color.to_value()),
+            Property("seed", ..) => Ok(settings.seed.to_value()),
+            Property("is-live", ..) => Ok(settings.is_live.to_value()),
+            Property("barcode", ..) => Ok(settings.barcode.to_value()),
+            Property("location", ..) => Ok(settings.location.to_value()),
+            Property("image-duration", ..) => Ok(settings.image_duration.to_value()),
+            Property("loop", ..) => Ok(settings.looping.to_value()),
+            Property("flash", ..) => Ok(settings.flash.to_value()),
+            Property("cache", ..) => Ok(settings.cache.to_value()),
+            _ => unimplemented!(),
+        }
+    }
 }
 
 impl ElementImpl for MySrc {}
 
 impl BaseSrcImpl for MySrc {
+    fn start(&self, src: &BaseSrc) -> Result<(), ErrorMessage> {
+        let settings = self.settings.lock().unwrap();
+        let slideshow = match settings.location {
+            None => None,
+            Some(ref location) => {
+                let paths = sequence_paths(location);
+                if paths.is_empty() {
+                    return Err(gst_error_msg!(
+                        ResourceError::NotFound,
+                        ["No images found at {}", location]
+                    ));
+                }
+                gst_debug!(self.cat, obj: src, "Showing {} images", paths.len());
+                Some(Slideshow {
+                    paths,
+                    current: None,
+                })
+            }
+        };
+        *self.slideshow.lock().unwrap() = slideshow;
+        *self.position.lock().unwrap() = Position::Seeked;
+        gst_info!(self.cat, obj: src, "Started");
+        Ok(())
+    }
+
+    fn stop(&self, src: &BaseSrc) -> Result<(), ErrorMessage> {
+        *self.cache.lock().unwrap() = None;
+        if let Some(pool) = self.buffer_pool.lock().unwrap().take() {
+            let _ = pool.set_active(false);
+        }
+        gst_info!(self.cat, obj: src, "Stopped");
+        Ok(())
+    }
+
     fn set_caps(&self, src: &BaseSrc, outcaps: &Caps) -> Result<(), LoggableError> {
         let out_info = VideoInfo::from_caps(outcaps)
             .ok_or_else(|| gst_loggable_error!(self.cat, "Failed to get video info"))?;
         gst_debug!(self.cat, obj: src, "Configured for caps {}", outcaps);
+
         *self.out_info.lock().unwrap() = Some(out_info);
         Ok(())
     }
 
-    fn fill(
-        &self,
-        src: &BaseSrc,
-        _offset: u64,
-        _length: u32,
-        buffer: &mut BufferRef,
-    ) -> Result<FlowSuccess, FlowError> {
+    fn fixate(&self, src: &BaseSrc, caps: Caps) -> Caps {
+        // Pick 720p30 if downstream doesn't care
+        let mut caps = Caps::truncate(caps);
+        {
+            let caps = caps.make_mut();
+            let s = caps.get_mut_structure(0).unwrap();
+            s.fixate_field_nearest_int("width", DEFAULT_WIDTH);
+            s.fixate_field_nearest_int("height", DEFAULT_HEIGHT);
+            s.fixate_field_nearest_fraction("framerate", Fraction::new(DEFAULT_FPS, 1));
+        }
+        gst_debug!(self.cat, obj: src, "Fixating caps {}", caps);
+        self.parent_fixate(src, caps)
+    }
+
+    fn is_seekable(&self, _src: &BaseSrc) -> bool {
+        !self.settings.lock().unwrap().is_live
+    }
+
+    fn do_seek(&self, src: &BaseSrc, segment: &mut Segment) -> bool {
+        let segment = match segment.downcast_ref::<ClockTime>() {
+            Some(segment) => segment,
+            None => {
+                gst_error!(self.cat, obj: src, "Can only seek in time");
+                return false;
+            }
+        };
+        // Playing backwards starts at the stop position, and the stream has no end.
+        if segment.get_rate() < 0.0 && segment.get_stop().is_none() {
+            gst_error!(self.cat, obj: src, "Reverse playback needs a stop position");
+            return false;
+        }
+        gst_debug!(self.cat, obj: src, "Seeking to {:?}", segment);
+        *self.position.lock().unwrap() = Position::Seeked;
+        true
+    }
+
+    fn query(&self, src: &BaseSrc, query: &mut QueryRef) -> bool {
+        if let QueryView::Convert(ref mut q) = query.view_mut() {
+            // Frames and times are related by the framerate.
+            let (value, dest_format) = q.get();
+            let out_info = self.out_info.lock().unwrap();
+            let fps = match out_info.as_ref() {
+                Some(out_info) => out_info.fps(),
+                None => return false,
+            };
+            let result = match (value, dest_format) {
+                (GenericFormattedValue::Time(time), Format::Default) => {
+                    GenericFormattedValue::Default(DefaultFormat(
+                        time.nseconds().map(|_| frame_at(fps, time)),
+                    ))
+                }
+                (GenericFormattedValue::Default(DefaultFormat(frame)), Format::Time) => {
+                    GenericFormattedValue::Time(
+                        frame.map_or(ClockTime::none(), |frame| frame_time(fps, frame)),
+                    )
+                }
+                (value, dest_format) if value.get_format() == dest_format => value,
+                _ => return false,
+            };
+            gst_debug!(self.cat, obj: src, "Converted {:?} to {:?}", value, result);
+            q.set(value, result);
+            return true;
+        }
+        if let QueryView::Latency(ref mut q) = query.view_mut() {
+            // A live source can't produce a frame until the end of it has passed.
+            let is_live = self.settings.lock().unwrap().is_live;
+            let out_info = self.out_info.lock().unwrap();
+            if let Some(out_info) = out_info.as_ref() {
+                let zero = ClockTime::from_nseconds(0);
+                let (min, max) = if !is_live {
+                    (zero, zero)
+                } else if *out_info.fps().numer() == 0 {
+                    // A still image has no frame duration, and could take any time
+                    (zero, ClockTime::none())
+                } else {
+                    let latency = frame_time(out_info.fps(), 1);
+                    (latency, latency)
+                };
+                gst_debug!(self.cat, obj: src, "Returning latency {} to {}", min, max);
+                q.set(is_live, min, max);
+                return true;
+            }
+            return false;
+        }
+        BaseSrcImplExt::parent_query(self, src, query)
+    }
+
+    fn unlock(&self, src: &BaseSrc) -> Result<(), ErrorMessage> {
+        gst_debug!(self.cat, obj: src, "Unlocking");
+        self.clock_wait.unlock();
+        Ok(())
+    }
+
+    fn unlock_stop(&self, src: &BaseSrc) -> Result<(), ErrorMessage> {
+        gst_debug!(self.cat, obj: src, "Unlock stop");
+        self.clock_wait.unlock_stop();
+        Ok(())
+    }
+
+    fn create(&self, src: &BaseSrc, _offset: u64, _length: u32) -> Result<Buffer, FlowError> {
         let out_guard = self.out_info.lock().map_err(|_| {
             gst_element_error!(src, CoreError::Negotiation, ["Lock poisoned"]);
             FlowError::NotNegotiated
@@ -111,53 +534,311 @@ impl BaseSrcImpl for MySrc {
             gst_element_error!(src, CoreError::Negotiation, ["Caps not set yet"]);
             FlowError::NotNegotiated
         })?;
-        let mut out_frame =
-            VideoFrameRef::from_buffer_ref_writable(buffer, out_info).ok_or_else(|| {
-                gst_element_error!(
-                    src,
-                    CoreError::Failed,
-                    ["Failed to map output buffer writable"]
+
+        let settings = self.settings.lock().unwrap().clone();
+        let mut slideshow = self.slideshow.lock().unwrap();
+
+        let segment = src.get_segment();
+        let segment = segment.downcast_ref::<ClockTime>().ok_or_else(|| {
+            gst_element_error!(src, CoreError::Failed, ["Segment not in time"]);
+            FlowError::Error
+        })?;
+        let reverse = segment.get_rate() < 0.0;
+
+        // Everything about the frame is derived from its number, so output is reproducible.
+        let mut position = self.position.lock().unwrap();
+        let mut frame = match *position {
+            Position::Seeked if reverse => {
+                let stop = segment.get_stop().nseconds().unwrap_or(0);
+                frame_at(
+                    out_info.fps(),
+                    ClockTime::from_nseconds(stop.saturating_sub(1)),
+                )
+            }
+            Position::Seeked => frame_at(out_info.fps(), segment.get_start()),
+            Position::Frame(frame) => frame,
+            Position::Done => return Err(FlowError::Eos),
+        };
+        let mut discont = false;
+        if settings.is_live {
+            let fps = out_info.fps();
+            let skipped = skip_missed(self.cat, src, frame, 1, |now| frame_at(fps, now));
+            frame = skipped.0;
+            discont = skipped.1;
+        }
+
+        let pts = frame_time(out_info.fps(), frame);
+        let end = frame_time(out_info.fps(), frame + 1);
+        let past_stop = segment.get_stop().is_some() && pts >= segment.get_stop();
+        let before_start = end.is_some() && end <= segment.get_start();
+        if (!reverse && past_stop) || (reverse && before_start) {
+            gst_debug!(self.cat, obj: src, "Reached the end of the segment");
+            *position = Position::Done;
+            return Err(FlowError::Eos);
+        }
+        let image_index = match *slideshow {
+            None => None,
+            Some(ref slideshow) => {
+                let index = pts.nseconds().unwrap_or(0) / settings.image_duration;
+                let count = slideshow.paths.len() as u64;
+                if index >= count && !settings.looping {
+                    gst_debug!(self.cat, obj: src, "Reached the end of the slideshow");
+                    *position = Position::Done;
+                    return Err(FlowError::Eos);
+                }
+                Some((index % count) as usize)
+            }
+        };
+        // With a variable framerate the only frame has no end, and there's no next one
+        *position = if reverse {
+            frame.checked_sub(1).map_or(Position::Done, Position::Frame)
+        } else if end.is_none() {
+            Position::Done
+        } else {
+            Position::Frame(frame + 1)
+        };
+        drop(position);
+
+        // Static frames are painted once, then later frames share the same memory.
+        let flash = settings.flash && has_sync_point(pts, end);
+        let cacheable = settings.cache
+            && !settings.barcode
+            && !flash
+            && (image_index.is_some() || settings.pattern.is_static());
+        let mut cache = self.cache.lock().unwrap();
+        let cached = match *cache {
+            Some((index, ref buffer)) if cacheable && index == image_index => {
+                gst_debug!(self.cat, obj: src, "Sharing cached frame");
+                Some(buffer.copy())
+            }
+            _ => None,
+        };
+        let mut buffer = match cached {
+            Some(buffer) => buffer,
+            None => {
+                let mut buffer = self.acquire_buffer(out_info)?;
+                let mut out_frame =
+                    VideoFrameRef::from_buffer_ref_writable(buffer.get_mut().unwrap(), out_info)
+                        .ok_or_else(|| {
+                            gst_element_error!(
+                                src,
+                                CoreError::Failed,
+                                ["Failed to map output buffer writable"]
+                            );
+                            FlowError::Error
+                        })?;
+                let height = out_frame.height() as usize;
+                let width = out_frame.width() as usize;
+                let format = out_frame.format();
+                gst_debug!(
+                    self.cat,
+                    obj: src,
+                    "Filling mysrc buffer {}x{} {:?}",
+                    width,
+                    height,
+                    format
                 );
-                FlowError::Error
-            })?;
-        let height = out_frame.height() as usize;
-        let width = out_frame.width() as usize;
-        let stride = out_frame.plane_stride()[0] as usize;
-        let format = out_frame.format();
-        let data = out_frame.plane_data_mut(0).unwrap();
+
+                let params = PatternParams {
+                    width: width as u32,
+                    height: height as u32,
+                    time: Duration::from_nanos(pts.nseconds().unwrap_or(0)),
+                    frame,
+                    foreground: Color::from_argb(settings.foreground_color),
+                    seed: settings.seed,
+                    barcode: if settings.barcode {
+                        segment
+                            .to_running_time(pts)
+                            .nseconds()
+                            .map(|nanos| nanos / 1000)
+                    } else {
+                        None
+                    },
+                };
+                let painter: &dyn Paint = match (slideshow.as_mut(), image_index) {
+                    _ if flash => &Flash,
+                    (Some(slideshow), Some(index)) => self.load_image(src, slideshow, index)?,
+                    _ => &settings.pattern,
+                };
+                if !paint_frame(painter, &params, &mut out_frame) {
+                    gst_element_error!(
+                        src,
+                        CoreError::Negotiation,
+                        ["Unsupported format {:?}", format]
+                    );
+                    return Err(FlowError::NotNegotiated);
+                }
+                drop(out_frame);
+
+                if cacheable {
+                    let shared = buffer.copy();
+                    *cache = Some((image_index, buffer));
+                    shared
+                } else {
+                    buffer
+                }
+            }
+        };
+        drop(cache);
+        drop(out_guard);
+        drop(slideshow);
+
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts);
+            buffer.set_duration(end - pts);
+            buffer.set_offset(frame);
+            buffer.set_offset_end(frame + 1);
+            if discont {
+                buffer.set_flags(BufferFlags::DISCONT);
+            }
+        }
+
+        // A live source only outputs a frame once the end of it has been reached.
+        if settings.is_live {
+            let jitter = self.clock_wait.wait_until(src, end)?;
+            gst_debug!(self.cat, obj: src, "Waited with jitter {}", jitter);
+        }
+
+        Ok(buffer)
+    }
+}
+
+impl MySrc {
+    // Replaces the pool BaseSrc picked with a video pool for the negotiated caps,
+    // keeping downstream's buffer counts. Called again when renegotiating.
+    fn decide_allocation(&self, src: &BaseSrc, query: &mut QueryRef) -> Result<(), LoggableError> {
+        let mut q = match query.view_mut() {
+            QueryView::Allocation(q) => q,
+            _ => return Err(gst_loggable_error!(self.cat, "Not an allocation query")),
+        };
+        let outcaps = q.get_owned().0;
+        let out_info = VideoInfo::from_caps(&outcaps)
+            .ok_or_else(|| gst_loggable_error!(self.cat, "Failed to get video info"))?;
+        let video_meta = q.find_allocation_meta::<VideoMeta>().is_some();
+        let (size, min_buffers, max_buffers) = q.get_allocation_pools().into_iter().next().map_or(
+            (0, 0, 0),
+            |(pool, size, min, max)| {
+                // BaseSrc has already configured the pool it chose
+                pool.and_then(|pool| pool.get_config().get_params())
+                    .map_or((size, min, max), |(_, size, min, max)| (size, min, max))
+            },
+        );
+
+        // Create a new video buffer pool with the negotiated caps.
+        // If downstream understands video meta, we can pad the strides for alignment.
+        let pool = VideoBufferPool::new();
+        let mut config = pool.get_config();
+        let size = size.max(out_info.size() as u32);
+        config.set_params(Some(&outcaps), size, min_buffers, max_buffers);
+        if video_meta {
+            config.add_option(&BUFFER_POOL_OPTION_VIDEO_META);
+            config.add_option(&BUFFER_POOL_OPTION_VIDEO_ALIGNMENT);
+            config.set_video_alignment(&VideoAlignment::new(0, 0, 0, 0, &[STRIDE_ALIGN; 4]));
+        }
+        pool.set_config(config)
+            .map_err(|_| gst_loggable_error!(self.cat, "Failed to update config"))?;
         gst_debug!(
             self.cat,
             obj: src,
-            "Filling mysrc buffer {}x{} {:?}",
-            width,
-            height,
-            format
+            "Allocating from a pool with{} video meta",
+            if video_meta { "" } else { "out" }
         );
 
-        let millis = self.start.elapsed().subsec_millis();
-        let brightness = if millis < 500 {
-            millis / 2
+        // Replace the old pool, and anything rendered with it
+        *self.cache.lock().unwrap() = None;
+        let pool = pool.upcast::<BufferPool>();
+        let old_pool = self.buffer_pool.lock().unwrap().replace(pool.clone());
+        if let Some(old_pool) = old_pool {
+            let _ = old_pool.set_active(false);
+        }
+
+        // So BaseSrc hands downstream the pool we allocate from
+        if q.get_allocation_pools().is_empty() {
+            q.add_allocation_pool(Some(&pool), size, min_buffers, max_buffers);
         } else {
-            (1000 - millis) / 2
-        } as u8;
-
-        if format == VideoFormat::Bgrx {
-            assert_eq!(data.len() % 4, 0);
-            let line_bytes = width * 4;
-            assert!(line_bytes <= stride);
-
-            for line in data.chunks_exact_mut(stride) {
-                for pixel in line[..line_bytes].chunks_exact_mut(4) {
-                    pixel[0] = brightness;
-                    pixel[1] = brightness / 2;
-                    pixel[2] = brightness / 4;
-                    pixel[3] = 0;
+            q.set_nth_allocation_pool(0, Some(&pool), size, min_buffers, max_buffers);
+        }
+        Ok(())
+    }
+
+    // A buffer to paint into, from our pool if we have one.
+    fn acquire_buffer(&self, out_info: &VideoInfo) -> Result<Buffer, FlowError> {
+        let pool_guard = self.buffer_pool.lock().unwrap();
+        match *pool_guard {
+            Some(ref pool) => {
+                if !pool.is_active() {
+                    pool.set_active(true).map_err(|_| FlowError::Error)?;
                 }
+                pool.acquire_buffer(None)
             }
-        } else {
-            unimplemented!();
+            None => Buffer::with_size(out_info.size()).ok_or(FlowError::Error),
+        }
+    }
+
+    // The image at the given index of the slideshow, loaded if it isn't the current one.
+    fn load_image<'a>(
+        &self,
+        src: &BaseSrc,
+        slideshow: &'a mut Slideshow,
+        index: usize,
+    ) -> Result<&'a Image, FlowError> {
+        let loaded = match slideshow.current {
+            Some((current, _)) => current == index,
+            None => false,
+        };
+        if !loaded {
+            let path = &slideshow.paths[index];
+            gst_debug!(self.cat, obj: src, "Loading {}", path);
+            let image = Image::load(path).map_err(|err| {
+                match err {
+                    ImageError::Io(ref io_err) if io_err.kind() == io::ErrorKind::NotFound => {
+                        gst_element_error!(
+                            src,
+                            ResourceError::NotFound,
+                            ("Image {} not found", path),
+                            ["{}", err]
+                        )
+                    }
+                    ImageError::Io(..) => gst_element_error!(
+                        src,
+                        ResourceError::Read,
+                        ("Failed to read image {}", path),
+                        ["{}", err]
+                    ),
+                    ImageError::Malformed(..) => gst_element_error!(
+                        src,
+                        StreamError::Decode,
+                        ("Failed to decode image {}", path),
+                        ["{}", err]
+                    ),
+                }
+                FlowError::Error
+            })?;
+            slideshow.current = Some((index, image));
         }
+        Ok(&slideshow.current.as_ref().unwrap().1)
+    }
+}
 
-        Ok(FlowSuccess::Ok)
+unsafe extern "C" fn decide_allocation_trampoline(
+    ptr: *mut GstBaseSrc,
+    query: *mut GstQuery,
+) -> gboolean {
+    let instance = &*(ptr as *mut ElementInstanceStruct<MySrc>);
+    let imp = instance.get_impl();
+    let src: BaseSrc = from_glib_borrow(ptr);
+    // Let BaseSrc pick a pool and read downstream's answer first
+    let parent_class = MySrc::type_data().as_ref().get_parent_class() as *mut GstBaseSrcClass;
+    if let Some(f) = (*parent_class).decide_allocation {
+        if f(ptr, query) == GFALSE {
+            return GFALSE;
+        }
+    }
+    let query = QueryRef::from_mut_ptr(query);
+    if let Err(err) = imp.decide_allocation(&src, query) {
+        err.log_with_object(&src);
+        return GFALSE;
     }
+    GTRUE
 }
@@ -0,0 +1,286 @@
+use crate::enums::register_enum_type;
+
+use glib::Type;
+
+use std::f64::consts::PI;
+use std::sync::Once;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    Pulse = 0,
+    SmpteBars = 1,
+    Checkerboard = 2,
+    HorizontalGradient = 3,
+    VerticalGradient = 4,
+    Solid = 5,
+    ZonePlate = 6,
+    Ball = 7,
+    Snow = 8,
+}
+
+impl Pattern {
+    pub fn get_type() -> Type {
+        static ONCE: Once = Once::new();
+        static mut TYPE: Type = Type::Invalid;
+        ONCE.call_once(|| {
+            let type_ = register_enum_type(
+                "MySrcPattern",
+                &[
+                    (Pattern::Pulse as i32, "Pulsing orange", "pulse"),
+                    (Pattern::SmpteBars as i32, "SMPTE colour bars", "smpte"),
+                    (Pattern::Checkerboard as i32, "Checkerboard", "checkerboard"),
+                    (
+                        Pattern::HorizontalGradient as i32,
+                        "Horizontal gradient",
+                        "h-gradient",
+                    ),
+                    (
+                        Pattern::VerticalGradient as i32,
+                        "Vertical gradient",
+                        "v-gradient",
+                    ),
+                    (Pattern::Solid as i32, "Solid colour", "solid-color"),
+                    (Pattern::ZonePlate as i32, "Zone plate", "zone-plate"),
+                    (Pattern::Ball as i32, "Moving ball", "ball"),
+                    (Pattern::Snow as i32, "Seeded random noise", "snow"),
+                ],
+            );
+            unsafe { TYPE = type_ };
+        });
+        unsafe { TYPE }
+    }
+
+    pub fn from_i32(value: i32) -> Option<Pattern> {
+        match value {
+            0 => Some(Pattern::Pulse),
+            1 => Some(Pattern::SmpteBars),
+            2 => Some(Pattern::Checkerboard),
+            3 => Some(Pattern::HorizontalGradient),
+            4 => Some(Pattern::VerticalGradient),
+            5 => Some(Pattern::Solid),
+            6 => Some(Pattern::ZonePlate),
+            7 => Some(Pattern::Ball),
+            8 => Some(Pattern::Snow),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+
+    pub const fn gray(y: u8) -> Color {
+        Color { r: y, g: y, b: y }
+    }
+
+    // Colours given as 0xAARRGGBB, as used by videotestsrc.
+    pub fn from_argb(argb: u32) -> Color {
+        Color::rgb((argb >> 16) as u8, (argb >> 8) as u8, argb as u8)
+    }
+}
+
+// Everything a pattern needs to know to paint a frame.
+pub struct PatternParams {
+    pub width: u32,
+    pub height: u32,
+    pub time: Duration,
+    pub frame: u64,
+    pub foreground: Color,
+    pub seed: u32,
+    // The running time in microseconds to encode as a barcode, if any.
+    pub barcode: Option<u64>,
+}
+
+// Anything that can be painted a line at a time.
+pub trait Paint {
+    fn paint_line(&self, params: &PatternParams, y: u32, line: &mut [Color]);
+}
+
+impl Paint for Pattern {
+    fn paint_line(&self, params: &PatternParams, y: u32, line: &mut [Color]) {
+        Pattern::paint_line(*self, params, y, line)
+    }
+}
+
+const BLACK: Color = Color::gray(0);
+const WHITE: Color = Color::gray(255);
+
+// 75% bars, as in SMPTE EG 1-1990.
+const SMPTE_TOP: [Color; 7] = [
+    Color::gray(191),
+    Color::rgb(191, 191, 0),
+    Color::rgb(0, 191, 191),
+    Color::rgb(0, 191, 0),
+    Color::rgb(191, 0, 191),
+    Color::rgb(191, 0, 0),
+    Color::rgb(0, 0, 191),
+];
+
+const SMPTE_MIDDLE: [Color; 7] = [
+    Color::rgb(0, 0, 191),
+    BLACK,
+    Color::rgb(191, 0, 191),
+    BLACK,
+    Color::rgb(0, 191, 191),
+    BLACK,
+    Color::gray(191),
+];
+
+const MINUS_I: Color = Color::rgb(0, 33, 76);
+const PLUS_Q: Color = Color::rgb(50, 0, 106);
+const SUPER_BLACK: Color = Color::gray(0);
+const PLUGE_BLACK: Color = Color::gray(16);
+const GRAY_BLACK: Color = Color::gray(26);
+
+impl Pattern {
+    // Whether the pattern looks the same in every frame.
+    pub fn is_static(self) -> bool {
+        match self {
+            Pattern::SmpteBars
+            | Pattern::Checkerboard
+            | Pattern::HorizontalGradient
+            | Pattern::VerticalGradient
+            | Pattern::Solid => true,
+            Pattern::Pulse | Pattern::ZonePlate | Pattern::Ball | Pattern::Snow => false,
+        }
+    }
+
+    // Paints line `y` of the frame.
+    pub fn paint_line(self, params: &PatternParams, y: u32, line: &mut [Color]) {
+        let width = params.width;
+        let height = params.height;
+        match self {
+            Pattern::Pulse => {
+                let millis = params.time.subsec_millis();
+                let brightness = if millis < 500 {
+                    millis / 2
+                } else {
+                    (1000 - millis) / 2
+                } as u8;
+                let color = Color::rgb(brightness / 4, brightness / 2, brightness);
+                fill(line, color);
+            }
+            Pattern::SmpteBars => {
+                if y < height * 2 / 3 {
+                    paint_bars(line, &SMPTE_TOP);
+                } else if y < height * 3 / 4 {
+                    paint_bars(line, &SMPTE_MIDDLE);
+                } else {
+                    // -I, white, +Q and black over the first five sevenths,
+                    // then the PLUGE (below black, black, above black) and black.
+                    for (x, pixel) in line.iter_mut().enumerate() {
+                        let x = x as u32;
+                        let quarter = x * 4 * 7 / (width * 5).max(1);
+                        let color = if x * 7 < width * 5 {
+                            match quarter {
+                                0 => MINUS_I,
+                                1 => WHITE,
+                                2 => PLUS_Q,
+                                _ => PLUGE_BLACK,
+                            }
+                        } else {
+                            match (x * 7 - width * 5) * 3 / width.max(1) {
+                                0 => SUPER_BLACK,
+                                1 => PLUGE_BLACK,
+                                2 => GRAY_BLACK,
+                                _ => PLUGE_BLACK,
+                            }
+                        };
+                        *pixel = color;
+                    }
+                }
+            }
+            Pattern::Checkerboard => {
+                const SQUARE: u32 = 8;
+                for (x, pixel) in line.iter_mut().enumerate() {
+                    let odd = ((x as u32 / SQUARE) + (y / SQUARE)) % 2 == 1;
+                    *pixel = if odd { WHITE } else { BLACK };
+                }
+            }
+            Pattern::HorizontalGradient => {
+                let last = width.saturating_sub(1).max(1);
+                for (x, pixel) in line.iter_mut().enumerate() {
+                    *pixel = Color::gray((x as u32 * 255 / last) as u8);
+                }
+            }
+            Pattern::VerticalGradient => {
+                let last = height.saturating_sub(1).max(1);
+                fill(line, Color::gray((y * 255 / last) as u8));
+            }
+            Pattern::Solid => fill(line, params.foreground),
+            Pattern::ZonePlate => {
+                // The spatial frequency rises with distance from the centre,
+                // reaching the Nyquist limit at the edges of the frame.
+                let scale = PI / f64::from(width.max(height));
+                let phase = params.time.as_secs_f64() * PI;
+                let cy = f64::from(y) - f64::from(height) / 2.0;
+                for (x, pixel) in line.iter_mut().enumerate() {
+                    let cx = x as f64 - f64::from(width) / 2.0;
+                    let value = ((cx * cx + cy * cy) * scale + phase).cos();
+                    *pixel = Color::gray(unit_to_u8(0.5 + value / 2.0));
+                }
+            }
+            Pattern::Ball => {
+                let radius = f64::from(width.min(height)) / 10.0;
+                let t = params.time.as_secs_f64();
+                let cx =
+                    f64::from(width) / 2.0 + (f64::from(width) / 2.0 - radius) * (t * PI).sin();
+                let cy = f64::from(height) / 2.0
+                    + (f64::from(height) / 2.0 - radius) * (t * PI * 2.0 / 3.0).sin();
+                let dy = f64::from(y) + 0.5 - cy;
+                for (x, pixel) in line.iter_mut().enumerate() {
+                    let dx = x as f64 + 0.5 - cx;
+                    // One pixel of antialiasing at the edge of the ball.
+                    let coverage = radius + 0.5 - (dx * dx + dy * dy).sqrt();
+                    *pixel = Color::gray(unit_to_u8(coverage.clamp(0.0, 1.0)));
+                }
+            }
+            Pattern::Snow => {
+                for (x, pixel) in line.iter_mut().enumerate() {
+                    let noise = hash(&[params.seed, params.frame as u32, x as u32, y]);
+                    *pixel = Color::gray(noise as u8);
+                }
+            }
+        }
+    }
+}
+
+fn fill(line: &mut [Color], color: Color) {
+    for pixel in line {
+        *pixel = color;
+    }
+}
+
+fn paint_bars(line: &mut [Color], bars: &[Color]) {
+    let width = line.len();
+    for (x, pixel) in line.iter_mut().enumerate() {
+        *pixel = bars[x * bars.len() / width];
+    }
+}
+
+fn unit_to_u8(value: f64) -> u8 {
+    (value * 255.0 + 0.5) as u8
+}
+
+// A small integer hash, so that noise only depends on the seed, the frame and the position.
+fn hash(values: &[u32]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for value in values {
+        hash ^= *value;
+        hash = hash.wrapping_mul(0x0100_0193);
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(0x2c1b_3c6d);
+        hash ^= hash >> 12;
+    }
+    hash
+}
@@ -0,0 +1,75 @@
+use crate::pattern::Color;
+use crate::pattern::Paint;
+use crate::pattern::PatternParams;
+
+use gstreamer::CapsRef;
+use gstreamer::ClockTime;
+use gstreamer::SECOND;
+
+// mysrc flashes and myaudiosrc beeps at the start of every second of the stream,
+// which is a frame boundary at any integer framerate.
+pub const SYNC_INTERVAL: u64 = 1_000_000_000;
+
+// The beep lasts long enough to be heard, and to survive resampling.
+pub const BEEP_DURATION: u64 = 50_000_000;
+pub const BEEP_FREQUENCY: f64 = 1000.0;
+
+// Whether a sync point falls in the span of a buffer.
+pub fn has_sync_point(start: ClockTime, end: ClockTime) -> bool {
+    match (start.nseconds(), end.nseconds()) {
+        (Some(start), Some(end)) => {
+            let next = start.div_ceil(SYNC_INTERVAL) * SYNC_INTERVAL;
+            next < end
+        }
+        (Some(start), None) => start % SYNC_INTERVAL == 0,
+        _ => false,
+    }
+}
+
+// Whether a sample at the given time is part of a beep.
+pub fn in_beep(time: u64) -> bool {
+    time % SYNC_INTERVAL < BEEP_DURATION
+}
+
+// The time of a sample, relative to the first sample.
+pub fn sample_time(rate: u32, sample: u64) -> u64 {
+    (sample as u128 * SECOND.nseconds().unwrap() as u128 / rate.max(1) as u128) as u64
+}
+
+// The whole frame goes white for a flash.
+pub struct Flash;
+
+impl Paint for Flash {
+    fn paint_line(&self, _params: &PatternParams, _y: u32, line: &mut [Color]) {
+        for pixel in line {
+            *pixel = Color::gray(255);
+        }
+    }
+}
+
+// The audio format used by myaudiosrc and myavsyncsink:
+// interleaved signed 16 bit little-endian samples.
+#[derive(Clone, Copy, Debug)]
+pub struct AudioInfo {
+    pub rate: u32,
+    pub channels: u32,
+}
+
+impl AudioInfo {
+    pub fn from_caps(caps: &CapsRef) -> Option<AudioInfo> {
+        let s = caps.get_structure(0)?;
+        let rate = s.get::<i32>("rate")?;
+        let channels = s.get::<i32>("channels")?;
+        if rate <= 0 || channels <= 0 {
+            return None;
+        }
+        Some(AudioInfo {
+            rate: rate as u32,
+            channels: channels as u32,
+        })
+    }
+
+    pub fn bytes_per_frame(&self) -> usize {
+        self.channels as usize * 2
+    }
+}
@@ -0,0 +1,68 @@
+use gstreamer::gst_debug;
+use gstreamer::ClockExt;
+use gstreamer::ClockTime;
+use gstreamer::DebugCategory;
+use gstreamer::ElementExt;
+use gstreamer::Fraction;
+use gstreamer::SECOND;
+use gstreamer_base::prelude::BaseSrcExtManual;
+use gstreamer_base::BaseSrc;
+
+// Frames are numbered from the start of the stream. A variable framerate (0/1)
+// means a still image, so there's only one frame: it starts at zero and never ends.
+
+// The time of the start of a frame, relative to the first frame.
+pub fn frame_time(fps: Fraction, frame: u64) -> ClockTime {
+    let numer = *fps.numer() as u128;
+    let denom = *fps.denom() as u128;
+    if numer == 0 {
+        return if frame == 0 {
+            ClockTime::from_nseconds(0)
+        } else {
+            ClockTime::none()
+        };
+    }
+    let nanos = frame as u128 * SECOND.nseconds().unwrap() as u128 * denom / numer;
+    ClockTime::from_nseconds(nanos as u64)
+}
+
+// The number of the frame showing at a time, relative to the first frame.
+pub fn frame_at(fps: Fraction, time: ClockTime) -> u64 {
+    let numer = *fps.numer() as u128;
+    let denom = *fps.denom() as u128;
+    match time.nseconds() {
+        Some(nanos) if numer != 0 => {
+            (nanos as u128 * numer / (SECOND.nseconds().unwrap() as u128 * denom)) as u64
+        }
+        _ => 0,
+    }
+}
+
+// After a pause, live sources skip what they missed rather than playing catch-up.
+// Given the next frame (or sample) and how far ahead of the clock a source may be,
+// returns the one to produce next and whether that's a discontinuity. `at`
+// converts a position in the stream to a frame number.
+pub fn skip_missed<F: FnOnce(ClockTime) -> u64>(
+    cat: DebugCategory,
+    src: &BaseSrc,
+    next: u64,
+    ahead: u64,
+    at: F,
+) -> (u64, bool) {
+    let clock = match src.get_clock() {
+        Some(clock) => clock,
+        None => return (next, false),
+    };
+    let now = clock.get_time() - src.get_base_time();
+    let segment = src.get_segment();
+    let now = match segment.downcast_ref::<ClockTime>() {
+        Some(segment) => segment.position_from_running_time(now),
+        None => now,
+    };
+    let current = at(now);
+    if current <= next.saturating_add(ahead) {
+        return (next, false);
+    }
+    gst_debug!(cat, obj: src, "Skipping from {} to {}", next, current);
+    (current, true)
+}
@@ -0,0 +1,102 @@
+// Shared by the integration tests, which each use some of it.
+#![allow(dead_code)]
+
+use glib::object::Cast;
+#[cfg(target_os = "linux")]
+use glib::ObjectExt;
+use gstreamer::Buffer;
+use gstreamer::Caps;
+use gstreamer::Element;
+use gstreamer::ElementExt;
+use gstreamer::ElementExtManual;
+use gstreamer::GstBinExt;
+use gstreamer::MessageView;
+use gstreamer::PadExt;
+use gstreamer::PadExtManual;
+use gstreamer::PadProbeData;
+use gstreamer::PadProbeReturn;
+use gstreamer::PadProbeType;
+use gstreamer::State;
+use gstreamer::SECOND;
+use gstreamer_gl::GLContext;
+use gstreamer_gl::GLContextExt;
+use gstreamer_gl::GLDisplay;
+#[cfg(target_os = "linux")]
+use gstreamer_gl::GLDisplayEGL;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Once;
+
+static REGISTER: Once = Once::new();
+
+pub fn init() {
+    gstreamer::init().unwrap();
+    REGISTER.call_once(|| gstmyplugin::plugin_desc::plugin_register_static().unwrap());
+}
+
+// Fails unless GStreamer can make a GL context here, trying the same displays as
+// myglsrc. Tests needing GL are ignored, since CI machines may not have it.
+pub fn require_gl() {
+    let display = GLDisplay::new();
+    let err = match GLContext::new(&display).create(None::<&GLContext>) {
+        Ok(()) => return,
+        Err(err) => err,
+    };
+    #[cfg(target_os = "linux")]
+    {
+        if !display.is::<GLDisplayEGL>() {
+            let egl_display = GLDisplayEGL::new().upcast::<GLDisplay>();
+            if GLContext::new(&egl_display)
+                .create(None::<&GLContext>)
+                .is_ok()
+            {
+                return;
+            }
+        }
+    }
+    panic!("No GL context: {}", err);
+}
+
+// Calls a function with every buffer reaching the element called "sink", and its caps.
+pub fn on_buffer<F>(pipeline: &Element, f: F)
+where
+    F: Fn(&Buffer, &Caps) + Send + Sync + 'static,
+{
+    let sink = pipeline
+        .downcast_ref::<gstreamer::Bin>()
+        .and_then(|bin| bin.get_by_name("sink"))
+        .expect("No sink");
+    let pad = sink.get_static_pad("sink").unwrap();
+    pad.add_probe(PadProbeType::BUFFER, move |pad, info| {
+        if let Some(PadProbeData::Buffer(ref buffer)) = info.data {
+            f(buffer, &pad.get_current_caps().unwrap());
+        }
+        PadProbeReturn::Ok
+    });
+}
+
+// Plays a pipeline to the end, returning the buffers that reach the sink.
+pub fn run(description: &str) -> Vec<(Buffer, Caps)> {
+    let pipeline = gstreamer::parse_launch(description).unwrap();
+    let buffers = Arc::new(Mutex::new(Vec::new()));
+    let received = buffers.clone();
+    on_buffer(&pipeline, move |buffer, caps| {
+        received
+            .lock()
+            .unwrap()
+            .push((buffer.clone(), caps.clone()));
+    });
+    pipeline.set_state(State::Playing).unwrap();
+    let bus = pipeline.get_bus().unwrap();
+    for message in bus.iter_timed(10 * SECOND) {
+        match message.view() {
+            MessageView::Eos(..) => break,
+            MessageView::Error(err) => panic!("{}: {:?}", err.get_error(), err.get_debug()),
+            _ => (),
+        }
+    }
+    pipeline.set_state(State::Null).unwrap();
+    let buffers = buffers.lock().unwrap().clone();
+    buffers
+}
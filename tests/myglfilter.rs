@@ -0,0 +1,66 @@
+mod common;
+
+use gstreamer_video::VideoFormat;
+use gstreamer_video::VideoFrame;
+use gstreamer_video::VideoInfo;
+
+// The RGB of each pixel in the only frame a pipeline produces.
+fn rgb_pixels(description: &str, format: VideoFormat) -> Vec<[u8; 3]> {
+    let buffers = common::run(description);
+    assert_eq!(buffers.len(), 1);
+    let (buffer, caps) = buffers.into_iter().next().unwrap();
+    let info = VideoInfo::from_caps(&caps).unwrap();
+    assert_eq!(info.format(), format);
+    let frame = VideoFrame::from_buffer_readable(buffer, &info).unwrap();
+    let stride = frame.plane_stride()[0] as usize;
+    let data = frame.plane_data(0).unwrap();
+    let mut pixels = Vec::new();
+    for y in 0..info.height() as usize {
+        for x in 0..info.width() as usize {
+            let p = &data[y * stride + x * 4..][..4];
+            pixels.push(match format {
+                VideoFormat::Bgrx => [p[2], p[1], p[0]],
+                _ => [p[0], p[1], p[2]],
+            });
+        }
+    }
+    pixels
+}
+
+// Needs GL, so run with `cargo test -- --ignored`
+#[test]
+#[ignore]
+fn matches_mytransform() {
+    common::init();
+    common::require_gl();
+    let src = "videotestsrc num-buffers=1 pattern=smpte";
+    let cpu = rgb_pixels(
+        &format!(
+            "{} ! video/x-raw,format=BGRx,width=64,height=48 ! mytransform ! \
+             video/x-raw,format=BGRx ! fakesink name=sink",
+            src
+        ),
+        VideoFormat::Bgrx,
+    );
+    let gpu = rgb_pixels(
+        &format!(
+            "{} ! video/x-raw,format=RGBA,width=64,height=48 ! glupload ! myglfilter ! \
+             gldownload ! video/x-raw,format=RGBA ! fakesink name=sink",
+            src
+        ),
+        VideoFormat::Rgba,
+    );
+    assert_eq!(cpu.len(), gpu.len());
+    for (index, (cpu, gpu)) in cpu.iter().zip(gpu.iter()).enumerate() {
+        for channel in 0..3 {
+            let diff = (i32::from(cpu[channel]) - i32::from(gpu[channel])).abs();
+            assert!(
+                diff <= 1,
+                "Pixel {} is {:?} on the CPU but {:?} on the GPU",
+                index,
+                cpu,
+                gpu
+            );
+        }
+    }
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use glib::object::Cast;
+use glib::translate::from_glib_none;
+use glib::ObjectExt;
+use gstreamer::BufferPool;
+use gstreamer::BufferPoolExt;
+use gstreamer::Caps;
+use gstreamer::ElementExt;
+use gstreamer::ElementExtManual;
+use gstreamer::GstBinExt;
+use gstreamer::MessageView;
+use gstreamer::MiniObject;
+use gstreamer::State;
+use gstreamer::MSECOND;
+use gstreamer_video::VideoInfo;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+// The size of the last buffer to reach the sink, and the pool it came from.
+type Last = Arc<Mutex<Option<((u32, u32), Option<BufferPool>)>>>;
+
+// Waits until a buffer of a size arrives, returning its pool.
+fn wait_for_size(pipeline: &gstreamer::Element, last: &Last, size: (u32, u32)) -> BufferPool {
+    let bus = pipeline.get_bus().unwrap();
+    let start = Instant::now();
+    loop {
+        if let Some(message) = bus.timed_pop(100 * MSECOND) {
+            if let MessageView::Error(err) = message.view() {
+                panic!("{}: {:?}", err.get_error(), err.get_debug());
+            }
+        }
+        if let Some((received, Some(ref pool))) = *last.lock().unwrap() {
+            if received == size {
+                return pool.clone();
+            }
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "No {:?} buffers",
+            size
+        );
+    }
+}
+
+// Needs GL, so run with `cargo test -- --ignored`
+#[test]
+#[ignore]
+fn renegotiates_size_while_playing() {
+    common::init();
+    common::require_gl();
+    // GL memory, so the buffers come from myglsrc's own pool
+    let pipeline = gstreamer::parse_launch(
+        "myglsrc ! capsfilter name=filter \
+         caps=video/x-raw(memory:GLMemory),format=RGBA,width=64,height=48 ! \
+         fakesink name=sink",
+    )
+    .unwrap();
+    let last: Last = Arc::new(Mutex::new(None));
+    let received = last.clone();
+    // Buffers aren't kept, so the pool doesn't run out
+    common::on_buffer(&pipeline, move |buffer, caps| {
+        let info = VideoInfo::from_caps(caps).unwrap();
+        let pool = unsafe { from_glib_none((*buffer.as_ptr()).pool) };
+        *received.lock().unwrap() = Some(((info.width(), info.height()), pool));
+    });
+    pipeline.set_state(State::Playing).unwrap();
+    let old_pool = wait_for_size(&pipeline, &last, (64, 48));
+
+    let filter = pipeline
+        .downcast_ref::<gstreamer::Bin>()
+        .and_then(|bin| bin.get_by_name("filter"))
+        .unwrap();
+    let caps =
+        Caps::from_string("video/x-raw(memory:GLMemory),format=RGBA,width=32,height=24").unwrap();
+    filter.set_property("caps", &caps).unwrap();
+    let new_pool = wait_for_size(&pipeline, &last, (32, 24));
+
+    assert_ne!(old_pool, new_pool);
+    assert!(!old_pool.is_active());
+    pipeline.set_state(State::Null).unwrap();
+}